@@ -0,0 +1,95 @@
+//! Compares the fused, parallel `boids_system` against the original
+//! layout of three serial systems. Run with `cargo bench`.
+
+use std::time::{Duration, Instant};
+
+use bevy::{
+    prelude::*,
+    ecs::schedule::{Stage, SystemStage},
+};
+use bevy_boids_demo::{
+    boids::*,
+    bounds::Bounds,
+    observe::*,
+    simulation::SimulationSettings,
+    velocitator::Velocitator,
+    Observe,
+    Simulation,
+};
+use rand::prelude::*;
+
+const RUNS: u32 = 100;
+
+/// Makes a world with the given number of boids, observed and ready to go.
+fn make_app(count: usize) -> App {
+    let bounds = Bounds::new(50.0, 0.0, 1000.0, 0.0, 250.0, 0.0, 1000.0, 50.0);
+
+    let mut app = App::new();
+    app
+        .insert_resource(SimulationSettings {
+            lockstep: true,
+            ..Default::default()
+        })
+        .add_plugins(MinimalPlugins)
+        .add_plugin(Simulation)
+        .add_plugin(Observe)
+        .insert_resource(StuffsToObserve::from_bounds(&bounds));
+
+    let mut rng = StdRng::seed_from_u64(0);
+    for _ in 0..count {
+        let position = Vec3::new(
+            rng.gen_range(bounds.x_min..bounds.x_max),
+            rng.gen_range(bounds.y_min..bounds.y_max),
+            rng.gen_range(bounds.z_min..bounds.z_max),
+        );
+        let velocity = Vec3::new(rng.gen_range(-1.0..1.0), 0.0, rng.gen_range(-1.0..1.0)) * 75.0;
+        app.world.spawn()
+            .insert_bundle((
+                Transform::from_translation(position),
+                Observer {
+                    perception_radius: 50.0,
+                    fov_half_angle: 2.6,
+                    max_neighbours: Some(7),
+                    ..Default::default()
+                },
+                Velocitator { velocity },
+                Separation { radius: 15.0, ..Default::default() },
+                Alignment::default(),
+                Cohesion::default(),
+            ));
+    }
+
+    app.insert_resource(bounds);
+
+    // The hash is built after the observed lists, so it takes two updates
+    // for everyone to see their neighbours.
+    app.update();
+    app.update();
+    app
+}
+
+fn time_stage(app: &mut App, mut stage: SystemStage) -> Duration {
+    // Warm up.
+    stage.run(&mut app.world);
+
+    let start = Instant::now();
+    for _ in 0..RUNS {
+        stage.run(&mut app.world);
+    }
+    start.elapsed() / RUNS
+}
+
+fn main() {
+    println!("{:>8} {:>14} {:>14}", "boids", "three systems", "fused");
+    for count in [500, 2000, 5000] {
+        let mut app = make_app(count);
+
+        let serial = time_stage(&mut app, SystemStage::parallel()
+            .with_system(separation_system)
+            .with_system(alignment_system)
+            .with_system(cohesion_system));
+        let fused = time_stage(&mut app, SystemStage::single(boids_system));
+
+        println!("{:>8} {:>14?} {:>14?}", count, serial, fused);
+    }
+}
@@ -32,6 +32,9 @@ pub struct ModelSpawned {}
 pub struct StartAnim {
     pub name: String,
     pub loop_plz: bool,
+    /// Hold still this far (in seconds) into the clip instead of playing
+    /// it, e.g. for a resting pose.
+    pub pose_at: Option<f32>,
 }
 
 /* This multi-phase approach is bad and hacky and is only here 
@@ -43,12 +46,14 @@ pub struct StartAnim {
 struct StartAnimPhase2 {
     clip: Handle<AnimationClip>,
     loop_plz: bool,
+    pose_at: Option<f32>,
 }
 
 #[derive(Component, Debug)]
 struct StartAnimPhase3 {
     clip: Handle<AnimationClip>,
     loop_plz: bool,
+    pose_at: Option<f32>,
 }
 
 fn start_anim_system_phase_1(
@@ -67,6 +72,7 @@ fn start_anim_system_phase_1(
                 {
                     clip: gltf.named_animations[&start_anim.name].clone_weak(),
                     loop_plz: start_anim.loop_plz,
+                    pose_at: start_anim.pose_at,
                 });
                 commands.entity(parent.get()).remove::<StartAnim>();
             }
@@ -86,6 +92,7 @@ fn start_anim_system_phase_2(
             {
                 clip: play_animation.clip.clone_weak(),
                 loop_plz: play_animation.loop_plz,
+                pose_at: play_animation.pose_at,
             });
             commands.entity(parent.get()).remove::<StartAnimPhase2>();
         }
@@ -100,7 +107,11 @@ fn start_anim_system_phase_3(
 {
     for (parent, mut player) in q_child.iter_mut() {
         if let Ok(play_animation) = q_parent.get(parent.get()) {
-            if play_animation.loop_plz {
+            if let Some(pose_at) = play_animation.pose_at {
+                player.play(play_animation.clip.clone_weak())
+                    .set_elapsed(pose_at)
+                    .pause();
+            } else if play_animation.loop_plz {
                 player.play(play_animation.clip.clone_weak())
                     .repeat();
             } else {
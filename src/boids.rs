@@ -1,10 +1,14 @@
 use bevy::{
     prelude::*,
 };
+use serde::{Deserialize, Serialize};
 use crate::observe;
 use observe::*;
 use crate::velocitator;
 use velocitator::*;
+use crate::flight::Grounded;
+use crate::simulation::{SimulationLabel, SimulationStage};
+use crate::steering::{SteeringAppExt, SteeringBehaviour};
 
 /// The boids plugin.
 pub struct Boids;
@@ -12,42 +16,133 @@ pub struct Boids;
 impl Plugin for Boids {
     fn build(&self, app: &mut App) {
         app
-            .add_system(separation_system)
-            .add_system(alignment_system)
-            .add_system(cohesion_system);
+            .add_system_to_stage(
+                SimulationStage,
+                boids_system
+                    .label(SimulationLabel::Boids)
+                    .after(SimulationLabel::Observe),
+            )
+            .add_steering_behaviour::<Separation>()
+            .add_steering_behaviour::<Alignment>()
+            .add_steering_behaviour::<Cohesion>();
     }
 }
 
-#[derive(Reflect, Component, Default)]
+/// How many boids each parallel task works through.
+const BOIDS_BATCH_SIZE: usize = 32;
+
+/// The separation radius to start from, if nothing says otherwise.
+pub const DEFAULT_SEPARATION_RADIUS: f32 = 15.0;
+
+#[derive(Reflect, Component)]
 #[reflect(Component)]
 pub struct Separation {
     pub separation_factor: Vec3,
     pub weight: f32,
+    /// Neighbours closer than this are pushed away from.
+    pub radius: f32,
+    #[reflect(ignore)]
+    pub falloff: SeparationFalloff,
 }
 
-fn separation_system(
-    mut query_us: Query<(&Transform, &mut Separation, &Observer, Entity)>,
-    query_others: Query<&Transform>,
-) {
-    for (transform, mut separation, observable, entity) in query_us.iter_mut() {
-        let mut away = Vec3::ZERO;
-        let observed = &observable.observed;
-        for ent_nearby in observed.into_iter()
-        {
-            if *ent_nearby == entity { continue; }
+impl Default for Separation {
+    fn default() -> Self {
+        Separation {
+            separation_factor: Vec3::ZERO,
+            weight: 0.0,
+            radius: DEFAULT_SEPARATION_RADIUS,
+            falloff: SeparationFalloff::default(),
+        }
+    }
+}
 
-            if let Ok(other_transform) = query_others.get(*ent_nearby)
-            {
-                let displacement = other_transform.translation - transform.translation;
+impl SteeringBehaviour for Separation {
+    fn steer(&self) -> Vec3 {
+        self.separation_factor
+    }
 
-                if displacement.length() < 15.0
-                {
-                    away -= displacement;
-                }
-            }
+    fn weight(&self) -> f32 {
+        self.weight
+    }
+}
+
+/// How the push away from a neighbour changes with its distance.
+/// All of them fall to zero at the separation radius.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SeparationFalloff {
+    /// Falls off linearly from the radius at contact to zero.
+    #[default]
+    Linear,
+    /// Grows with the inverse of the distance.
+    Inverse,
+    /// Grows with the inverse square of the distance.
+    InverseSquare,
+}
+
+impl SeparationFalloff {
+    pub const ALL: [SeparationFalloff; 3] = [
+        SeparationFalloff::Linear,
+        SeparationFalloff::Inverse,
+        SeparationFalloff::InverseSquare,
+    ];
+
+    /// How hard to push away from a neighbour at the given distance.
+    /// This is in units of distance, so the weights behave much the same
+    /// whichever falloff is used.
+    pub fn strength(&self, distance: f32, radius: f32) -> f32 {
+        if radius <= 0.0 || distance >= radius {
+            return 0.0;
+        }
+
+        // Avoid blowing up when neighbours are right on top of each other.
+        let distance = distance.max(radius * 0.05);
+        match self {
+            SeparationFalloff::Linear => radius - distance,
+            SeparationFalloff::Inverse => radius * (radius / distance - 1.0),
+            SeparationFalloff::InverseSquare => radius * ((radius / distance).powi(2) - 1.0),
         }
+    }
+}
+
+/// Works out the separation factor from our neighbours' positions.
+pub fn separation_factor(
+    position: Vec3,
+    radius: f32,
+    falloff: SeparationFalloff,
+    neighbour_positions: impl Iterator<Item=Vec3>,
+) -> Vec3 {
+    let mut away = Vec3::ZERO;
+    for other_position in neighbour_positions
+    {
+        let displacement = other_position - position;
+        let distance = displacement.length();
 
-        separation.separation_factor = away;
+        if distance < radius && distance > 0.0
+        {
+            away -= displacement / distance * falloff.strength(distance, radius);
+        }
+    }
+    away
+}
+
+/// The separation rule on its own. The plugin uses `boids_system`
+/// instead; this is kept around for comparison (see the benchmark).
+pub fn separation_system(
+    mut query_us: Query<(&Transform, &mut Separation, &Observer)>,
+    query_others: Query<&Transform>,
+) {
+    for (transform, mut separation, observable) in query_us.iter_mut() {
+        let observed = &observable.observed;
+        let neighbour_positions = observed.iter()
+            .filter_map(|ent_nearby| query_others.get(*ent_nearby).ok())
+            .map(|other_transform| other_transform.translation);
+
+        separation.separation_factor = separation_factor(
+            transform.translation,
+            separation.radius,
+            separation.falloff,
+            neighbour_positions,
+        );
     }
 }
 
@@ -58,33 +153,48 @@ pub struct Alignment {
     pub weight: f32,
 }
 
-fn alignment_system(
-    mut query_us: Query<(&mut Alignment, &Observer, &Velocitator, Entity)>,
+impl SteeringBehaviour for Alignment {
+    fn steer(&self) -> Vec3 {
+        self.alignment_factor
+    }
+
+    fn weight(&self) -> f32 {
+        self.weight
+    }
+}
+
+/// Works out the alignment factor from our neighbours' velocities.
+pub fn alignment_factor(velocity: Vec3, neighbour_velocities: impl Iterator<Item=Vec3>) -> Vec3 {
+    let mut align_vel = Vec3::ZERO;
+    let mut count = 0;
+
+    for other_velocity in neighbour_velocities
+    {
+        align_vel += other_velocity;
+        count += 1;
+    }
+
+    if count > 0 {
+        align_vel / count as f32 - velocity
+    } else {
+        Vec3::ZERO
+    }
+}
+
+/// The alignment rule on its own. The plugin uses `boids_system`
+/// instead; this is kept around for comparison (see the benchmark).
+pub fn alignment_system(
+    mut query_us: Query<(&mut Alignment, &Observer, &Velocitator)>,
     query_others: Query<&Velocitator>,
 )
 {
-    for (mut alignment, observable, velocitator, entity) in query_us.iter_mut() {
-        let mut align_vel = Vec3::ZERO;
-
+    for (mut alignment, observable, velocitator) in query_us.iter_mut() {
         let observed = &observable.observed;
-        let mut count = 0;
+        let neighbour_velocities = observed.iter()
+            .filter_map(|ent_nearby| query_others.get(*ent_nearby).ok())
+            .map(|other_velocitator| other_velocitator.velocity);
 
-        for ent_nearby in observed.into_iter()
-        {
-            if *ent_nearby == entity { continue; }
-
-            if let Ok(other_velocitator) = query_others.get(*ent_nearby)
-            {
-                align_vel += other_velocitator.velocity;
-                count += 1;
-            }
-        }
-
-        if count > 0 {
-            alignment.alignment_factor = align_vel / count as f32 - velocitator.velocity;
-        } else {
-            alignment.alignment_factor = Vec3::ZERO;
-        }
+        alignment.alignment_factor = alignment_factor(velocitator.velocity, neighbour_velocities);
     }
 }
 
@@ -95,29 +205,204 @@ pub struct Cohesion {
     pub weight: f32,
 }
 
-fn cohesion_system(
-    mut query_us: Query<(&Transform, &mut Cohesion, &Observer, Entity)>,
+impl SteeringBehaviour for Cohesion {
+    fn steer(&self) -> Vec3 {
+        self.cohesion_factor
+    }
+
+    fn weight(&self) -> f32 {
+        self.weight
+    }
+}
+
+/// Works out the cohesion factor from our neighbours' positions.
+pub fn cohesion_factor(position: Vec3, neighbour_positions: impl Iterator<Item=Vec3>) -> Vec3 {
+    let mut avg_pos = Vec3::ZERO;
+    let mut count = 0;
+
+    for other_position in neighbour_positions
+    {
+        avg_pos += other_position;
+        count += 1;
+    }
+
+    if count > 0 {
+        avg_pos / count as f32 - position
+    } else {
+        Vec3::ZERO
+    }
+}
+
+/// The cohesion rule on its own. The plugin uses `boids_system`
+/// instead; this is kept around for comparison (see the benchmark).
+pub fn cohesion_system(
+    mut query_us: Query<(&Transform, &mut Cohesion, &Observer)>,
     query_others: Query<&Transform>,
 ) {
-    for (transform, mut cohesion, observable, entity) in query_us.iter_mut() {
+    for (transform, mut cohesion, observable) in query_us.iter_mut() {
         let observed = &observable.observed;
-        let mut avg_pos = Vec3::ZERO;
-        let mut count = 0;
+        let neighbour_positions = observed.iter()
+            .filter_map(|ent_nearby| query_others.get(*ent_nearby).ok())
+            .map(|other_transform| other_transform.translation);
 
-        for ent_nearby in observed.into_iter()
-        {
-            if *ent_nearby == entity { continue; }
-            if let Ok(other_transform) = query_others.get(*ent_nearby)
-            {
-                avg_pos += other_transform.translation;
-                count += 1;
-            }
+        cohesion.cohesion_factor = cohesion_factor(transform.translation, neighbour_positions);
+    }
+}
+
+/// Which species (or flock) something belongs to. Alignment and cohesion
+/// only follow neighbours of the same species, while separation keeps
+/// away from everyone. Boids without one flock with anything.
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Species(pub usize);
+
+/// Whether two boids flock together.
+pub fn same_species(a: Option<&Species>, b: Option<&Species>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => a == b,
+        _ => true,
+    }
+}
+
+/// What we need to know about a neighbour, gathered once per boid.
+#[derive(Clone, Copy, Debug)]
+struct Neighbour {
+    position: Vec3,
+    velocity: Option<Vec3>,
+    same_species: bool,
+}
+
+/// All three rules in a single pass. Each boid looks up its neighbours
+/// once and the boids are split across the compute task pool.
+pub fn boids_system(
+    mut query_us: Query<
+        (&Transform, &Observer, Option<&Velocitator>, Option<&Species>, Option<&mut Separation>, Option<&mut Alignment>, Option<&mut Cohesion>),
+        (Or<(With<Separation>, With<Alignment>, With<Cohesion>)>, Without<Grounded>),
+    >,
+    query_others: Query<(&Transform, Option<&Velocitator>, Option<&Species>)>,
+    stuff_to_observe: Res<StuffsToObserve>,
+) {
+    query_us.par_for_each_mut(BOIDS_BATCH_SIZE, |(transform, observable, velocitator, species, separation, alignment, cohesion)| {
+        let position = transform.translation;
+
+        // Neighbours across a wrapped edge count as being just over it.
+        let neighbours: Vec<Neighbour> = observable.observed.iter()
+            .filter_map(|ent_nearby| query_others.get(*ent_nearby).ok())
+            .map(|(other_transform, other_velocitator, other_species)| Neighbour {
+                position: position + stuff_to_observe.displacement(position, other_transform.translation),
+                velocity: other_velocitator.map(|v| v.velocity),
+                same_species: same_species(species, other_species),
+            })
+            .collect();
+
+        if let Some(mut separation) = separation {
+            separation.separation_factor = separation_factor(
+                position,
+                separation.radius,
+                separation.falloff,
+                neighbours.iter().map(|n| n.position),
+            );
+        }
+
+        if let Some(mut alignment) = alignment {
+            // Alignment needs our own velocity, too.
+            alignment.alignment_factor = match velocitator {
+                Some(velocitator) => alignment_factor(
+                    velocitator.velocity,
+                    neighbours.iter().filter(|n| n.same_species).filter_map(|n| n.velocity),
+                ),
+                None => Vec3::ZERO,
+            };
+        }
+
+        if let Some(mut cohesion) = cohesion {
+            cohesion.cohesion_factor = cohesion_factor(
+                position,
+                neighbours.iter().filter(|n| n.same_species).map(|n| n.position),
+            );
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn species_flock_with_their_own() {
+        assert!(same_species(Some(&Species(1)), Some(&Species(1))));
+        assert!(!same_species(Some(&Species(1)), Some(&Species(2))));
+        assert!(same_species(None, Some(&Species(2))));
+        assert!(same_species(Some(&Species(1)), None));
+    }
+
+    #[test]
+    fn falloff_zero_outside_radius() {
+        for falloff in SeparationFalloff::ALL {
+            assert_eq!(falloff.strength(15.0, 15.0), 0.0);
+            assert_eq!(falloff.strength(20.0, 15.0), 0.0);
+        }
+    }
+
+    #[test]
+    fn falloff_stronger_when_closer() {
+        for falloff in SeparationFalloff::ALL {
+            assert!(falloff.strength(2.0, 15.0) > falloff.strength(10.0, 15.0));
         }
-        if count > 0 {
-            cohesion.cohesion_factor = avg_pos / count as f32 - transform.translation;
-        } else {
-            // Reset factor.
-            cohesion.cohesion_factor = Vec3::ZERO;
+    }
+
+    #[test]
+    fn falloff_finite_at_contact() {
+        for falloff in SeparationFalloff::ALL {
+            assert!(falloff.strength(0.0, 15.0).is_finite());
         }
     }
+
+    #[test]
+    fn falloff_inverse_square_steepest_up_close() {
+        let linear = SeparationFalloff::Linear.strength(3.0, 15.0);
+        let inverse = SeparationFalloff::Inverse.strength(3.0, 15.0);
+        let inverse_square = SeparationFalloff::InverseSquare.strength(3.0, 15.0);
+        assert!(linear < inverse);
+        assert!(inverse < inverse_square);
+    }
+
+    #[test]
+    fn separation_factor_points_away() {
+        let factor = separation_factor(
+            Vec3::ZERO,
+            15.0,
+            SeparationFalloff::Linear,
+            [Vec3::new(5.0, 0.0, 0.0)].into_iter(),
+        );
+        assert!(factor.x < 0.0);
+        assert_eq!(factor.y, 0.0);
+        assert_eq!(factor.z, 0.0);
+    }
+
+    #[test]
+    fn default_separation_has_a_radius() {
+        let separation = Separation::default();
+        assert_eq!(separation.radius, DEFAULT_SEPARATION_RADIUS);
+        assert!(separation.falloff.strength(5.0, separation.radius) > 0.0);
+    }
+
+    #[test]
+    fn alignment_factor_matches_average() {
+        let factor = alignment_factor(
+            Vec3::X,
+            [Vec3::Z, Vec3::Z * 3.0].into_iter(),
+        );
+        assert_eq!(factor, Vec3::new(-1.0, 0.0, 2.0));
+        assert_eq!(alignment_factor(Vec3::X, std::iter::empty()), Vec3::ZERO);
+    }
+
+    #[test]
+    fn cohesion_factor_points_to_centre() {
+        let factor = cohesion_factor(
+            Vec3::ZERO,
+            [Vec3::new(2.0, 0.0, 0.0), Vec3::new(0.0, 4.0, 0.0)].into_iter(),
+        );
+        assert_eq!(factor, Vec3::new(1.0, 2.0, 0.0));
+        assert_eq!(cohesion_factor(Vec3::ZERO, std::iter::empty()), Vec3::ZERO);
+    }
 }
@@ -1,3 +1,42 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// What happens at the edges of the bounds.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BoundaryMode {
+    /// Nothing hard; `KeepInBounds` steers things back inside the margin.
+    #[default]
+    Steer,
+    /// Leaving one side brings you back in on the other (classic boids).
+    /// Only x and z wrap, as the ground and sky stay where they are.
+    /// Only a box arena can wrap; the others reflect instead.
+    Wrap,
+    /// Solid walls which things bounce off.
+    Reflect,
+}
+
+/// The shape of the space inside the bounds.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ArenaShape {
+    /// The whole box.
+    #[default]
+    Box,
+    /// The largest sphere which fits in the middle of the box.
+    Sphere,
+    /// The largest upright cylinder which fits in the box, floor to ceiling.
+    Cylinder,
+}
+
+/// Where something ended up after `Bounds::contain`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Containment {
+    Inside,
+    /// Came in on the other side, so it mustn't be seen sliding there.
+    Wrapped(Vec3),
+    /// Hit a wall, so it's been put back on it and heading away.
+    Reflected { position: Vec3, direction: Vec3 },
+}
+
 pub struct Bounds {
     pub cell_size: f32,
     pub x_min: f32,
@@ -11,7 +50,10 @@ pub struct Bounds {
     pub z_size: f32,
     pub margin: f32,
     pub cells_x: usize,
+    pub cells_y: usize,
     pub cells_z: usize,
+    pub mode: BoundaryMode,
+    pub shape: ArenaShape,
 }
 
 impl Bounds {
@@ -20,6 +62,7 @@ impl Bounds {
         let y_size = y_max - y_min;
         let z_size = z_max - z_min;
         let cells_x = (x_size / cell_size) as usize;
+        let cells_y = (y_size / cell_size) as usize;
         let cells_z = (z_size / cell_size) as usize;
         Bounds {
             cell_size,
@@ -34,7 +77,192 @@ impl Bounds {
             y_size,
             z_size,
             cells_x,
+            cells_y,
             cells_z,
+            mode: BoundaryMode::default(),
+            shape: ArenaShape::default(),
+        }
+    }
+
+    pub fn centre(&self) -> Vec3 {
+        Vec3::new(
+            self.x_min + 0.5 * self.x_size,
+            self.y_min + 0.5 * self.y_size,
+            self.z_min + 0.5 * self.z_size,
+        )
+    }
+
+    /// The radius of a sphere or cylinder arena.
+    pub fn radius(&self) -> f32 {
+        let horizontal = self.x_size.min(self.z_size) * 0.5;
+        match self.shape {
+            ArenaShape::Sphere => horizontal.min(self.y_size * 0.5),
+            _ => horizontal,
+        }
+    }
+
+    /// Whether leaving one side brings you back on the other.
+    pub fn wraps(&self) -> bool {
+        self.mode == BoundaryMode::Wrap && self.shape == ArenaShape::Box
+    }
+
+    /// The shortest way from one point to another, which might be across
+    /// a wrapped edge.
+    pub fn displacement(&self, from: Vec3, to: Vec3) -> Vec3 {
+        let displacement = to - from;
+        if !self.wraps() {
+            return displacement;
+        }
+        Vec3::new(
+            wrap_offset(displacement.x, self.x_size),
+            displacement.y,
+            wrap_offset(displacement.z, self.z_size),
+        )
+    }
+
+    /// How far inside the curved wall of a sphere or cylinder arena we are
+    /// (negative outside), and the direction pointing out through it.
+    pub fn wall_distance(&self, position: Vec3) -> (f32, Vec3) {
+        let mut out = position - self.centre();
+        if self.shape == ArenaShape::Cylinder {
+            out.y = 0.0;
+        }
+        let distance = out.length();
+        let normal = if distance > 0.0 { out / distance } else { Vec3::ZERO };
+        (self.radius() - distance, normal)
+    }
+
+    /// Holds something at the given position, heading in the given
+    /// direction, to the hard edges of the bounds (if it has any).
+    pub fn contain(&self, position: Vec3, direction: Vec3) -> Containment {
+        match self.mode {
+            BoundaryMode::Steer => Containment::Inside,
+            BoundaryMode::Wrap if self.wraps() => {
+                let wrapped = Vec3::new(
+                    self.x_min + (position.x - self.x_min).rem_euclid(self.x_size),
+                    position.y,
+                    self.z_min + (position.z - self.z_min).rem_euclid(self.z_size),
+                );
+                if wrapped == position { Containment::Inside } else { Containment::Wrapped(wrapped) }
+            }
+            BoundaryMode::Wrap | BoundaryMode::Reflect => self.reflect(position, direction),
+        }
+    }
+
+    fn reflect(&self, position: Vec3, direction: Vec3) -> Containment {
+        let mut new_position = position;
+        let mut new_direction = direction;
+
+        let walls = |value: &mut f32, heading: &mut f32, min: f32, max: f32| {
+            if *value < min {
+                *value = min;
+                *heading = heading.abs();
+            } else if *value > max {
+                *value = max;
+                *heading = -heading.abs();
+            }
+        };
+        walls(&mut new_position.y, &mut new_direction.y, self.y_min, self.y_max);
+        if self.shape == ArenaShape::Box {
+            walls(&mut new_position.x, &mut new_direction.x, self.x_min, self.x_max);
+            walls(&mut new_position.z, &mut new_direction.z, self.z_min, self.z_max);
+        } else {
+            let (inside, normal) = self.wall_distance(new_position);
+            if inside < 0.0 {
+                new_position += normal * inside;
+                let heading_out = new_direction.dot(normal);
+                if heading_out > 0.0 {
+                    new_direction -= 2.0 * heading_out * normal;
+                }
+            }
+        }
+
+        if new_position == position {
+            Containment::Inside
+        } else {
+            Containment::Reflected { position: new_position, direction: new_direction }
+        }
+    }
+}
+
+/// The shortest equivalent of an offset along an axis which wraps every `size`.
+pub fn wrap_offset(offset: f32, size: f32) -> f32 {
+    if size <= 0.0 {
+        return offset;
+    }
+    offset - size * (offset / size).round()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bounds(mode: BoundaryMode, shape: ArenaShape) -> Bounds {
+        let mut bounds = Bounds::new(10.0, 0.0, 100.0, 0.0, 50.0, 0.0, 100.0, 10.0);
+        bounds.mode = mode;
+        bounds.shape = shape;
+        bounds
+    }
+
+    #[test]
+    fn steer_never_contains() {
+        let bounds = bounds(BoundaryMode::Steer, ArenaShape::Box);
+        assert_eq!(bounds.contain(Vec3::new(-50.0, 0.0, 0.0), Vec3::X), Containment::Inside);
+    }
+
+    #[test]
+    fn wrap_comes_in_the_other_side() {
+        let bounds = bounds(BoundaryMode::Wrap, ArenaShape::Box);
+        assert_eq!(bounds.contain(Vec3::new(50.0, 20.0, 50.0), Vec3::X), Containment::Inside);
+        assert_eq!(
+            bounds.contain(Vec3::new(102.0, 20.0, -1.0), Vec3::X),
+            Containment::Wrapped(Vec3::new(2.0, 20.0, 99.0)),
+        );
+    }
+
+    #[test]
+    fn wrapped_displacement_is_shortest() {
+        let bounds = bounds(BoundaryMode::Wrap, ArenaShape::Box);
+        let displacement = bounds.displacement(Vec3::new(95.0, 0.0, 50.0), Vec3::new(5.0, 10.0, 50.0));
+        assert!((displacement - Vec3::new(10.0, 10.0, 0.0)).length() < 1e-4);
+        let bounds = Bounds::new(10.0, 0.0, 100.0, 0.0, 50.0, 0.0, 100.0, 10.0);
+        assert_eq!(bounds.displacement(Vec3::new(95.0, 0.0, 50.0), Vec3::new(5.0, 0.0, 50.0)).x, -90.0);
+    }
+
+    #[test]
+    fn reflect_off_box_walls() {
+        let bounds = bounds(BoundaryMode::Reflect, ArenaShape::Box);
+        assert_eq!(
+            bounds.contain(Vec3::new(101.0, 20.0, 50.0), Vec3::new(1.0, 0.0, 0.0)),
+            Containment::Reflected { position: Vec3::new(100.0, 20.0, 50.0), direction: Vec3::new(-1.0, 0.0, 0.0) },
+        );
+    }
+
+    #[test]
+    fn reflect_off_sphere_wall() {
+        let bounds = bounds(BoundaryMode::Reflect, ArenaShape::Sphere);
+        assert_eq!(bounds.radius(), 25.0);
+        match bounds.contain(Vec3::new(80.0, 25.0, 50.0), Vec3::X) {
+            Containment::Reflected { position, direction } => {
+                assert!((position - Vec3::new(75.0, 25.0, 50.0)).length() < 1e-4);
+                assert!((direction - -Vec3::X).length() < 1e-4);
+            }
+            other => panic!("expected a reflection, got {:?}", other),
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn cylinder_wall_ignores_height() {
+        let bounds = bounds(BoundaryMode::Reflect, ArenaShape::Cylinder);
+        let (inside, normal) = bounds.wall_distance(Vec3::new(50.0, 40.0, 10.0));
+        assert!((inside - 10.0).abs() < 1e-4);
+        assert!((normal - -Vec3::Z).length() < 1e-4);
+    }
+
+    #[test]
+    fn only_boxes_wrap() {
+        let bounds = bounds(BoundaryMode::Wrap, ArenaShape::Cylinder);
+        assert!(!bounds.wraps());
+        assert!(matches!(bounds.contain(Vec3::new(50.0, 20.0, -5.0), Vec3::Z), Containment::Reflected { .. }));
+    }
+}
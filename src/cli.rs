@@ -0,0 +1,166 @@
+/// Options which can be passed on the command line, e.g.
+/// `cargo run -- --headless --ticks 600`.
+#[derive(Debug, Default)]
+pub struct CliArgs {
+    /// Run the simulation without a window, renderer or assets.
+    pub headless: bool,
+    /// How many ticks to run for when headless.
+    pub ticks: u32,
+    /// Simulation ticks per second.
+    pub tick_rate: f64,
+    /// The seed for everything random, if given.
+    pub seed: Option<u64>,
+    /// A scenario file to load instead of the default crow demo.
+    pub scenario: Option<String>,
+    /// A file to record the flock to from the start, as CSV if it ends in
+    /// `.csv` and in the binary format otherwise.
+    pub record: Option<String>,
+    /// A recording to play back instead of running the simulation.
+    pub replay: Option<String>,
+    /// A CSV file to write the flock's metrics to at the end of a headless run.
+    pub metrics: Option<String>,
+}
+
+/// The number of ticks a headless run lasts if `--ticks` isn't given.
+pub const DEFAULT_HEADLESS_TICKS: u32 = 600;
+
+/// The simulation tick rate if `--tick-rate` isn't given.
+pub const DEFAULT_TICK_RATE: f64 = 60.0;
+
+impl CliArgs {
+    pub fn from_env() -> CliArgs {
+        CliArgs::parse(std::env::args().skip(1))
+    }
+
+    /// Parses the arguments (not including the program name). Unknown
+    /// arguments are reported and otherwise ignored.
+    pub fn parse(args: impl IntoIterator<Item=String>) -> CliArgs {
+        let mut cli_args = CliArgs {
+            headless: false,
+            ticks: DEFAULT_HEADLESS_TICKS,
+            tick_rate: DEFAULT_TICK_RATE,
+            seed: None,
+            scenario: None,
+            record: None,
+            replay: None,
+            metrics: None,
+        };
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--headless" => cli_args.headless = true,
+                "--ticks" => {
+                    match args.next().map(|value| value.parse::<u32>()) {
+                        Some(Ok(ticks)) => cli_args.ticks = ticks,
+                        _ => eprintln!("--ticks expects a whole number, ignoring it."),
+                    }
+                }
+                "--tick-rate" => {
+                    match args.next().map(|value| value.parse::<f64>()) {
+                        Some(Ok(tick_rate)) if tick_rate > 0.0 => cli_args.tick_rate = tick_rate,
+                        _ => eprintln!("--tick-rate expects a positive number, ignoring it."),
+                    }
+                }
+                "--seed" => {
+                    match args.next().map(|value| value.parse::<u64>()) {
+                        Some(Ok(seed)) => cli_args.seed = Some(seed),
+                        _ => eprintln!("--seed expects a whole number, ignoring it."),
+                    }
+                }
+                "--scenario" => {
+                    match args.next() {
+                        Some(path) => cli_args.scenario = Some(path),
+                        None => eprintln!("--scenario expects a file, ignoring it."),
+                    }
+                }
+                "--record" => {
+                    match args.next() {
+                        Some(path) => cli_args.record = Some(path),
+                        None => eprintln!("--record expects a file, ignoring it."),
+                    }
+                }
+                "--replay" => {
+                    match args.next() {
+                        Some(path) => cli_args.replay = Some(path),
+                        None => eprintln!("--replay expects a file, ignoring it."),
+                    }
+                }
+                "--metrics" => {
+                    match args.next() {
+                        Some(path) => cli_args.metrics = Some(path),
+                        None => eprintln!("--metrics expects a file, ignoring it."),
+                    }
+                }
+                _ => eprintln!("Unknown argument: {}", arg),
+            }
+        }
+
+        cli_args
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> CliArgs {
+        CliArgs::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn parse_defaults() {
+        let args = parse(&[]);
+        assert!(!args.headless);
+        assert_eq!(args.ticks, DEFAULT_HEADLESS_TICKS);
+        assert_eq!(args.tick_rate, DEFAULT_TICK_RATE);
+        assert_eq!(args.seed, None);
+    }
+
+    #[test]
+    fn parse_scenario() {
+        let args = parse(&["--scenario", "assets/scenarios/default.ron"]);
+        assert_eq!(args.scenario.as_deref(), Some("assets/scenarios/default.ron"));
+    }
+
+    #[test]
+    fn parse_record() {
+        assert_eq!(parse(&["--headless", "--record", "flock.csv"]).record.as_deref(), Some("flock.csv"));
+        assert_eq!(parse(&[]).record, None);
+    }
+
+    #[test]
+    fn parse_replay() {
+        assert_eq!(parse(&["--replay", "flock.rec"]).replay.as_deref(), Some("flock.rec"));
+        assert_eq!(parse(&[]).replay, None);
+    }
+
+    #[test]
+    fn parse_metrics() {
+        assert_eq!(parse(&["--headless", "--metrics", "metrics.csv"]).metrics.as_deref(), Some("metrics.csv"));
+    }
+
+    #[test]
+    fn parse_seed() {
+        assert_eq!(parse(&["--seed", "1234"]).seed, Some(1234));
+    }
+
+    #[test]
+    fn parse_tick_rate() {
+        assert_eq!(parse(&["--tick-rate", "30"]).tick_rate, 30.0);
+        assert_eq!(parse(&["--tick-rate", "0"]).tick_rate, DEFAULT_TICK_RATE);
+    }
+
+    #[test]
+    fn parse_headless_ticks() {
+        let args = parse(&["--headless", "--ticks", "42"]);
+        assert!(args.headless);
+        assert_eq!(args.ticks, 42);
+    }
+
+    #[test]
+    fn parse_bad_ticks() {
+        let args = parse(&["--ticks", "lots"]);
+        assert_eq!(args.ticks, DEFAULT_HEADLESS_TICKS);
+    }
+}
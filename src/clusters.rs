@@ -0,0 +1,299 @@
+use std::collections::BTreeMap;
+
+use bevy::{
+    prelude::*,
+    utils::HashMap,
+};
+use crate::bounds::Bounds;
+use crate::flight::Grounded;
+use crate::observe::Observer;
+use crate::simulation::*;
+
+/// The clusters plugin. Every tick, splits everything with an `Observer`
+/// into flocks: two flyers are in the same flock if either can see the
+/// other within the linkage distance, and so on through their neighbours.
+/// Each flock big enough to count gets a `FlockId`, which carries on from
+/// tick to tick for as long as most of the flock does.
+pub struct Clusters;
+
+impl Plugin for Clusters {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<ClusterSettings>()
+            .init_resource::<FlockClusters>()
+            .add_system_to_stage(
+                SimulationStage,
+                cluster_system
+                    .label(SimulationLabel::Clusters)
+                    .after(SimulationLabel::Flight),
+            );
+    }
+}
+
+pub struct ClusterSettings {
+    /// The furthest apart two neighbours can be and still link their flocks.
+    pub linkage_distance: f32,
+    /// Anything smaller than this isn't a flock, just stragglers.
+    pub min_flock_size: usize,
+}
+
+impl Default for ClusterSettings {
+    fn default() -> Self {
+        ClusterSettings {
+            linkage_distance: 40.0,
+            min_flock_size: 2,
+        }
+    }
+}
+
+/// Which flock something is in.
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct FlockId(pub u32);
+
+/// How the flyers were split into flocks at the last tick.
+#[derive(Default)]
+pub struct FlockClusters {
+    /// The size of each flock, biggest first.
+    pub sizes: Vec<usize>,
+    /// How many flyers weren't in any flock.
+    pub stragglers: usize,
+    next_id: u32,
+}
+
+impl FlockClusters {
+    pub fn count(&self) -> usize {
+        self.sizes.len()
+    }
+
+    pub fn largest(&self) -> usize {
+        self.sizes.first().copied().unwrap_or(0)
+    }
+
+    pub fn mean_size(&self) -> f32 {
+        if self.sizes.is_empty() {
+            return 0.0;
+        }
+        self.sizes.iter().sum::<usize>() as f32 / self.sizes.len() as f32
+    }
+}
+
+/// A disjoint-set forest, for finding connected components.
+pub struct UnionFind {
+    parent: Vec<usize>,
+    size: Vec<usize>,
+}
+
+impl UnionFind {
+    pub fn new(count: usize) -> UnionFind {
+        UnionFind {
+            parent: (0..count).collect(),
+            size: vec![1; count],
+        }
+    }
+
+    pub fn find(&mut self, item: usize) -> usize {
+        let mut root = item;
+        while self.parent[root] != root {
+            root = self.parent[root];
+        }
+        // Point everything on the way straight at the root.
+        let mut item = item;
+        while self.parent[item] != root {
+            let next = self.parent[item];
+            self.parent[item] = root;
+            item = next;
+        }
+        root
+    }
+
+    pub fn union(&mut self, a: usize, b: usize) {
+        let (a, b) = (self.find(a), self.find(b));
+        if a == b {
+            return;
+        }
+        let (big, small) = if self.size[a] >= self.size[b] { (a, b) } else { (b, a) };
+        self.parent[small] = big;
+        self.size[big] += self.size[small];
+    }
+}
+
+/// Splits `count` things into connected components given the links
+/// between them. Each component's members are in order, and the components
+/// are in order of their first member.
+pub fn connected_components(count: usize, links: impl Iterator<Item=(usize, usize)>) -> Vec<Vec<usize>> {
+    let mut forest = UnionFind::new(count);
+    for (a, b) in links {
+        forest.union(a, b);
+    }
+
+    let mut components: Vec<Vec<usize>> = Vec::new();
+    let mut component_of_root: HashMap<usize, usize> = HashMap::default();
+    for item in 0..count {
+        let root = forest.find(item);
+        let component = *component_of_root.entry(root).or_insert_with(|| {
+            components.push(Vec::new());
+            components.len() - 1
+        });
+        components[component].push(item);
+    }
+    components
+}
+
+/// Picks an id for each flock, given what id (if any) each member had
+/// before. A flock keeps an old id if it carries over most of that old
+/// flock, so when a flock splits only a part with a majority keeps the id;
+/// anything left over gets a new id.
+pub fn assign_flock_ids(flocks: &[Vec<usize>], previous: &[Option<u32>], next_id: &mut u32) -> Vec<u32> {
+    let mut old_sizes: BTreeMap<u32, usize> = BTreeMap::new();
+    for id in previous.iter().flatten() {
+        *old_sizes.entry(*id).or_insert(0) += 1;
+    }
+
+    // (members carried over, flock, old id), best first.
+    let mut candidates = Vec::new();
+    for (flock, members) in flocks.iter().enumerate() {
+        let mut overlaps: BTreeMap<u32, usize> = BTreeMap::new();
+        for id in members.iter().filter_map(|member| previous[*member]) {
+            *overlaps.entry(id).or_insert(0) += 1;
+        }
+        candidates.extend(
+            overlaps
+                .into_iter()
+                .filter(|(id, overlap)| overlap * 2 > old_sizes[id])
+                .map(|(id, overlap)| (overlap, flock, id)),
+        );
+    }
+    candidates.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)).then(a.2.cmp(&b.2)));
+
+    let mut ids: Vec<Option<u32>> = vec![None; flocks.len()];
+    let mut taken = Vec::new();
+    for (_, flock, id) in candidates {
+        if ids[flock].is_none() && !taken.contains(&id) {
+            ids[flock] = Some(id);
+            taken.push(id);
+        }
+    }
+
+    ids.into_iter()
+        .map(|id| id.unwrap_or_else(|| {
+            *next_id += 1;
+            *next_id - 1
+        }))
+        .collect()
+}
+
+fn cluster_system(
+    mut commands: Commands,
+    settings: Res<ClusterSettings>,
+    bounds: Option<Res<Bounds>>,
+    mut clusters: ResMut<FlockClusters>,
+    query: Query<(Entity, &Transform, &Observer, Option<&FlockId>, Option<&Grounded>)>,
+) {
+    // Grounded flyers aren't flying with anyone.
+    let mut flyers: Vec<(Entity, Vec3, &Observer, Option<&FlockId>)> = Vec::new();
+    for (entity, transform, observer, flock_id, grounded) in query.iter() {
+        if grounded.is_some() {
+            if flock_id.is_some() {
+                commands.entity(entity).remove::<FlockId>();
+            }
+        } else {
+            flyers.push((entity, transform.translation, observer, flock_id));
+        }
+    }
+    flyers.sort_by_key(|(entity, ..)| *entity);
+    let index_of: HashMap<Entity, usize> = flyers.iter()
+        .enumerate()
+        .map(|(index, (entity, ..))| (*entity, index))
+        .collect();
+
+    let linkage_sqr = settings.linkage_distance * settings.linkage_distance;
+    let mut links = Vec::new();
+    for (us, (_, position, observer, _)) in flyers.iter().enumerate() {
+        for other in observer.observed.iter() {
+            if let Some(&them) = index_of.get(other) {
+                let displacement = match &bounds {
+                    Some(bounds) => bounds.displacement(*position, flyers[them].1),
+                    None => flyers[them].1 - *position,
+                };
+                if displacement.length_squared() <= linkage_sqr {
+                    links.push((us, them));
+                }
+            }
+        }
+    }
+    let mut flocks = connected_components(flyers.len(), links.into_iter());
+    flocks.retain(|members| members.len() >= settings.min_flock_size);
+
+    let previous: Vec<Option<u32>> = flyers.iter().map(|(.., flock_id)| flock_id.map(|flock_id| flock_id.0)).collect();
+    let ids = assign_flock_ids(&flocks, &previous, &mut clusters.next_id);
+
+    let mut new_ids: Vec<Option<u32>> = vec![None; flyers.len()];
+    for (members, id) in flocks.iter().zip(ids) {
+        for member in members.iter() {
+            new_ids[*member] = Some(id);
+        }
+    }
+    for ((entity, ..), (old_id, new_id)) in flyers.iter().zip(previous.iter().zip(new_ids.iter())) {
+        match new_id {
+            Some(id) if old_id != new_id => {
+                commands.entity(*entity).insert(FlockId(*id));
+            }
+            None if old_id.is_some() => {
+                commands.entity(*entity).remove::<FlockId>();
+            }
+            _ => {}
+        }
+    }
+
+    clusters.sizes = flocks.iter().map(|members| members.len()).collect();
+    clusters.sizes.sort_unstable_by(|a, b| b.cmp(a));
+    clusters.stragglers = flyers.len() - clusters.sizes.iter().sum::<usize>();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn components_follow_links() {
+        let components = connected_components(6, [(0, 2), (4, 2), (3, 5)].into_iter());
+        assert_eq!(components, vec![vec![0, 2, 4], vec![1], vec![3, 5]]);
+        assert_eq!(connected_components(0, std::iter::empty()), Vec::<Vec<usize>>::new());
+    }
+
+    #[test]
+    fn ids_carry_on() {
+        let mut next_id = 2;
+        let flocks = [vec![0, 1], vec![2, 3, 4]];
+        let previous = [Some(1), Some(1), Some(0), Some(0), None];
+        assert_eq!(assign_flock_ids(&flocks, &previous, &mut next_id), vec![1, 0]);
+        assert_eq!(next_id, 2);
+    }
+
+    #[test]
+    fn bigger_share_keeps_id_when_splitting() {
+        let mut next_id = 1;
+        let flocks = [vec![0], vec![1, 2], vec![3]];
+        let previous = [Some(0), Some(0), Some(0), None];
+        assert_eq!(assign_flock_ids(&flocks, &previous, &mut next_id), vec![1, 0, 2]);
+        assert_eq!(next_id, 3);
+    }
+
+    #[test]
+    fn even_split_gets_new_ids() {
+        let mut next_id = 1;
+        let flocks = [vec![0, 1], vec![2, 3]];
+        let previous = [Some(0); 4];
+        assert_eq!(assign_flock_ids(&flocks, &previous, &mut next_id), vec![1, 2]);
+        assert_eq!(next_id, 3);
+    }
+
+    #[test]
+    fn stats() {
+        let clusters = FlockClusters { sizes: vec![5, 3, 1], stragglers: 2, next_id: 0 };
+        assert_eq!(clusters.count(), 3);
+        assert_eq!(clusters.largest(), 5);
+        assert_eq!(clusters.mean_size(), 3.0);
+        assert_eq!(FlockClusters::default().mean_size(), 0.0);
+    }
+}
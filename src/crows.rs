@@ -1,7 +1,12 @@
+use std::f32::consts::TAU;
+
 use bevy::{
     prelude::*,
+    gltf::Gltf,
 };
 use big_brain::prelude::*;
+use rand::prelude::*;
+use serde::{Deserialize, Serialize};
 use crate::{anim, jay_math};
 use anim::*;
 use crate::boids;
@@ -10,13 +15,68 @@ use crate::observe;
 use observe::*;
 use crate::bounds;
 use bounds::*;
+use crate::food::FoodSource;
 use crate::flight;
 use flight::*;
+use crate::navigation::{seek_velocity, Arrive, NavTarget, Patrol, Seek};
+use crate::obstacles::{AvoidObstacles, Obstacle};
+use crate::predators::Flee;
+use crate::terrain::Terrain;
+use crate::scenario::{GoalConfig, HungerConfig, SpeciesConfig, WingbeatConfig};
+use crate::simulation::*;
+use crate::steering::*;
 use crate::velocitator::Velocitator;
 
+/// The crows plugin. Adds the crow-specific goals and the big-brain
+/// actions and scorers. Expects the `FlockingPlugins` and the
+/// `BigBrainPlugin` to be added, too.
+pub struct Crows;
+
+impl Plugin for Crows {
+    fn build(&self, app: &mut App) {
+        app
+            .add_system_set_to_stage(
+                SimulationStage,
+                SystemSet::new()
+                    .label(SimulationLabel::Goals)
+                    .after(SimulationLabel::Boids)
+                    .with_system(keep_in_bounds_system)
+                    .with_system(keep_level_system)
+                    .with_system(stamina_update_system)
+                    .with_system(wingbeat_system.after(keep_in_bounds_system))
+                    .with_system(perch_system)
+                    .with_system(hunger_system)
+                    .with_system(separation_settings_system)
+                    .with_system(species_weights_system),
+            )
+            .init_resource::<SpeciesWeights>()
+            .add_steering_behaviour::<KeepInBounds>()
+            .add_steering_behaviour::<KeepLevel>()
+            .add_steering_behaviour::<Perch>()
+            .add_steering_scale::<Separation, CrowGlobalProps>(|props| props.separation_weight)
+            .add_steering_scale::<Alignment, CrowGlobalProps>(|props| props.alignment_weight)
+            .add_steering_scale::<Cohesion, CrowGlobalProps>(|props| props.cohesion_weight)
+            .add_steering_scale::<KeepInBounds, CrowGlobalProps>(|props| props.keep_in_bounds_weight)
+            .add_steering_scale::<KeepLevel, CrowGlobalProps>(|props| props.keep_level_weight)
+            .add_steering_scale::<Flee, CrowGlobalProps>(|props| props.flee_weight)
+            .add_steering_scale::<AvoidObstacles, CrowGlobalProps>(|props| props.avoid_obstacles_weight)
+            .add_steering_scale::<Seek, CrowGlobalProps>(|props| props.seek_weight)
+            .add_steering_scale::<Arrive, CrowGlobalProps>(|props| props.seek_weight)
+            .add_steering_scale::<Patrol, CrowGlobalProps>(|props| props.seek_weight)
+            .add_system_to_stage(BigBrainStage::Actions, flap_action_system)
+            .add_system_to_stage(BigBrainStage::Actions, land_action_system)
+            .add_system_to_stage(BigBrainStage::Actions, forage_action_system)
+            .add_system_to_stage(BigBrainStage::Scorers, flap_scorer_system)
+            .add_system_to_stage(BigBrainStage::Scorers, land_scorer_system)
+            .add_system_to_stage(BigBrainStage::Scorers, forage_scorer_system);
+    }
+}
+
 /// Some global properties for our crows to use. Makes it possible
 /// to have a little UI with sliders to scale the weights even though
 /// individuals have their own weights, too.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct CrowGlobalProps
 {
     pub separation_weight: f32,
@@ -24,62 +84,254 @@ pub struct CrowGlobalProps
     pub cohesion_weight: f32,
     pub keep_in_bounds_weight: f32,
     pub keep_level_weight: f32,
+    pub flee_weight: f32,
+    pub avoid_obstacles_weight: f32,
+    /// For all of `Seek`, `Arrive` and `Patrol`.
+    pub seek_weight: f32,
+    /// Copied onto every crow's `Separation`.
+    pub separation_radius: f32,
+    /// Copied onto every crow's `Separation`.
+    pub separation_falloff: SeparationFalloff,
 }
 
+impl Default for CrowGlobalProps {
+    fn default() -> Self {
+        CrowGlobalProps {
+            separation_weight: 2.0,
+            alignment_weight: 2.0,
+            cohesion_weight: 2.0,
+            keep_in_bounds_weight: 0.5,
+            keep_level_weight: 0.5,
+            flee_weight: 3.0,
+            avoid_obstacles_weight: 3.0,
+            seek_weight: 1.0,
+            separation_radius: DEFAULT_SEPARATION_RADIUS,
+            separation_falloff: SeparationFalloff::Linear,
+        }
+    }
+}
+
+/// Pushes the global separation settings to each crow when they change,
+/// and to crows as they're spawned.
+pub fn separation_settings_system(
+    common_props: Res<CrowGlobalProps>,
+    mut queries: ParamSet<(
+        Query<&mut Separation>,
+        Query<&mut Separation, Added<Separation>>,
+    )>,
+) {
+    let set = |mut separation: Mut<Separation>| {
+        separation.radius = common_props.separation_radius;
+        separation.falloff = common_props.separation_falloff;
+    };
+    if common_props.is_changed() {
+        queries.p0().iter_mut().for_each(set);
+    } else {
+        queries.p1().iter_mut().for_each(set);
+    }
+}
+
+/// Each species' own weights, indexed by `Species`, so they can be
+/// changed during play. They're scaled by the global weights, too.
+#[derive(Default)]
+pub struct SpeciesWeights {
+    pub species: Vec<SpeciesWeightSet>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SpeciesWeightSet {
+    pub name: String,
+    pub separation: f32,
+    pub alignment: f32,
+    pub cohesion: f32,
+    pub keep_level: f32,
+}
+
+impl SpeciesWeights {
+    pub fn from_species(species: &[SpeciesConfig]) -> SpeciesWeights {
+        SpeciesWeights {
+            species: species.iter()
+                .map(|species| SpeciesWeightSet {
+                    name: species.name.clone(),
+                    separation: species.boids.separation,
+                    alignment: species.boids.alignment,
+                    cohesion: species.boids.cohesion,
+                    keep_level: species.boids.keep_level,
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Copies each species' weights to its members when they change, for
+/// whichever of the behaviours they have.
+pub fn species_weights_system(
+    weights: Res<SpeciesWeights>,
+    mut query: Query<(&Species, Option<&mut Separation>, Option<&mut Alignment>, Option<&mut Cohesion>, Option<&mut KeepLevel>)>,
+) {
+    if !weights.is_changed() {
+        return;
+    }
+    for (species, separation, alignment, cohesion, keep_level) in query.iter_mut() {
+        if let Some(set) = weights.species.get(species.0) {
+            if let Some(mut separation) = separation {
+                separation.weight = set.separation;
+            }
+            if let Some(mut alignment) = alignment {
+                alignment.weight = set.alignment;
+            }
+            if let Some(mut cohesion) = cohesion {
+                cohesion.weight = set.cohesion;
+            }
+            if let Some(mut keep_level) = keep_level {
+                keep_level.weight = set.keep_level;
+            }
+        }
+    }
+}
+
+/// How long (roughly) `Stamina::average` looks back over, in seconds.
+const STAMINA_AVERAGE_SECS: f32 = 30.0;
+
 // Stamina state.
 #[derive(Component, Debug)]
 pub struct Stamina {
     recover_per_second: f32,
+    rest_per_second: f32,
+    flap_usage_per_second: f32,
     current_value: f32,
+    /// A running average, so that one hard flap doesn't make us land.
+    average: f32,
 }
 
-pub fn stamina_update_system(time: Res<Time>, mut staminas: Query<&mut Stamina>) {
-    for mut stamina in staminas.iter_mut() {
-        stamina.current_value += stamina.recover_per_second * time.delta_seconds();
+impl Stamina {
+    pub fn current_value(&self) -> f32 {
+        self.current_value
+    }
+}
+
+pub fn stamina_update_system(
+    time: Res<SimulationTime>,
+    mut staminas: Query<(&mut Stamina, Option<&Wingbeat>, Option<&Grounded>)>,
+) {
+    for (mut stamina, wingbeat, grounded) in staminas.iter_mut() {
+        let flapping = wingbeat.map_or(false, |wingbeat| wingbeat.flapping);
+        let change = if grounded.is_some() {
+            stamina.rest_per_second
+        } else if flapping {
+            stamina.recover_per_second - stamina.flap_usage_per_second
+        } else {
+            stamina.recover_per_second
+        };
+        stamina.current_value += change * time.delta_seconds();
         if stamina.current_value >= 100.0 {
             stamina.current_value = 100.0;
         }
+        let blend = (time.delta_seconds() / STAMINA_AVERAGE_SECS).min(1.0);
+        stamina.average += (stamina.current_value - stamina.average) * blend;
         // println!("Stamina: {}", stamina.current_value);
     }
 }
 
-/// Our flapping action. 
-#[derive(Clone, Component, Debug)]
-pub struct Flap {
-    stamina_usage_per_sec: f32,
+/// Whether we're flapping or soaring, and what each lets us do. Flapping
+/// costs stamina (see `stamina_update_system`) but lets us speed up and
+/// climb; soaring is free but we can barely speed up and slowly sink.
+#[derive(Component, Debug)]
+pub struct Wingbeat {
+    pub flapping: bool,
+    pub flap_accel_max: f32,
+    pub flap_climb_max: f32,
+    pub soar_accel_max: f32,
+    pub soar_climb_max: f32,
+    pub soar_sink_rate: f32,
+}
+
+impl Wingbeat {
+    pub fn new(flap_accel_max: f32, config: &WingbeatConfig) -> Wingbeat {
+        Wingbeat {
+            flapping: false,
+            flap_accel_max,
+            flap_climb_max: config.flap_climb_max,
+            soar_accel_max: config.soar_accel_max,
+            soar_climb_max: config.soar_climb_max,
+            soar_sink_rate: config.soar_sink_rate,
+        }
+    }
+
+    /// Copies what this wingbeat lets us do onto a flyer's props. Below the
+    /// floor we fly as if flapping whatever we're doing, or soaring (which
+    /// can't climb at all by default) would leave us stuck down there.
+    pub fn apply(&self, props: &mut FlyerProps, below_floor: bool) {
+        if self.flapping || below_floor {
+            props.accel_max = self.flap_accel_max;
+            props.climb_max = self.flap_climb_max;
+            props.sink_rate = 0.0;
+        } else {
+            props.accel_max = self.soar_accel_max;
+            props.climb_max = self.soar_climb_max;
+            props.sink_rate = self.soar_sink_rate;
+        }
+    }
 }
 
+/// Copies what the current wingbeat lets us do onto the flyer. Aerodynamic
+/// flyers sink by themselves while soaring, as they just can't flap.
+pub fn wingbeat_system(
+    mut query: Query<(&Wingbeat, &mut Flyer, Option<&mut Aerodynamics>, Option<&KeepInBounds>)>,
+) {
+    for (wingbeat, mut flyer, aero, keep_in_bounds) in query.iter_mut() {
+        let below_floor = keep_in_bounds.map_or(false, |keep_in_bounds| keep_in_bounds.below_floor);
+        wingbeat.apply(&mut flyer.props, below_floor);
+        if let Some(mut aero) = aero {
+            aero.max_throttle = if wingbeat.flapping || below_floor { 1.0 } else { 0.0 };
+            flyer.props.sink_rate = 0.0;
+        }
+    }
+}
+
+/// Our flapping action. Flaps until we're down to half our stamina.
+#[derive(Clone, Component, Debug)]
+pub struct Flap;
+
 pub fn flap_action_system(
-    time: Res<Time>,
     mut commands: Commands,
-    mut staminas: Query<(&mut Stamina, Entity)>,
-    mut query_actor_stuff: Query<(&Actor, &mut ActionState, &Flap)>,
+    mut query_crows: Query<(&Stamina, &mut Wingbeat, Entity)>,
+    mut query_actor_stuff: Query<(&Actor, &mut ActionState), With<Flap>>,
 ) {
-    for (Actor(actor), mut state, flap) in query_actor_stuff.iter_mut() {
-        if let Ok((mut stamina, entity)) = staminas.get_mut(*actor) {
+    for (Actor(actor), mut state) in query_actor_stuff.iter_mut() {
+        if let Ok((stamina, mut wingbeat, entity)) = query_crows.get_mut(*actor) {
             match *state {
                 ActionState::Requested => {
                     *state = ActionState::Executing;
+                    wingbeat.flapping = true;
                     commands.entity(entity).insert(StartAnim {
                         name: String::from("Flap"),
                         loop_plz: true,
+                        pose_at: None,
                     });
                     commands.entity(entity).insert(ModelSpawned {});
                 }
                 ActionState::Executing => {
-                    stamina.current_value -=
-                        flap.stamina_usage_per_sec * time.delta_seconds();
+                    // The stamina itself is used up in the simulation.
                     if stamina.current_value <= 50.0 {
                         *state = ActionState::Success;
+                        wingbeat.flapping = false;
                         commands.entity(entity).insert(StartAnim {
                             name: String::from("Soar"),
                             loop_plz: true,
+                            pose_at: None,
                         });
                     }
                 }
                 // All Actions should make sure to handle cancellations!
                 ActionState::Cancelled => {
                     *state = ActionState::Failure;
+                    wingbeat.flapping = false;
+                    commands.entity(entity).insert(StartAnim {
+                        name: String::from("Soar"),
+                        loop_plz: true,
+                        pose_at: None,
+                    });
                 }
                 _ => {}
             }
@@ -93,27 +345,537 @@ pub struct FlapScorer;
 
 // Looks familiar? It's a lot like Actions!
 pub fn flap_scorer_system(
-    query_components: Query<(&Stamina, &Flyer)>,
+    query_components: Query<(&Stamina, &Flyer, Option<&Perch>)>,
     mut query_actor_stuff: Query<(&Actor, &mut Score), With<FlapScorer>>,
 ) {
     for (Actor(actor), mut score) in query_actor_stuff.iter_mut() {
-        if let Ok((stamina, flyer)) = query_components.get(*actor) {
+        if let Ok((stamina, flyer, perch)) = query_components.get(*actor) {
             let stamina_factor = jay_math::inv_lerp(50.0, 100.0, stamina.current_value).clamp(0.0, 1.0);
             let speed_factor = jay_math::inv_lerp(0.0, 3.0, flyer.goal_velocity.length() - flyer.speed_linear).clamp(0.0, 1.0);
 
             // The score here must be between 0.0 and 1.0.
-            score.set(stamina_factor * speed_factor);
+            // Landing does its own flapping.
+            score.set(if perch.is_some() { 0.0 } else { stamina_factor * speed_factor });
         }
     }
 }
 
-/// A simple goal to keep the crows relatively level, in lieu of an actual gravity/lift model.
+/// How close to the perch counts as having landed on it.
+const TOUCHDOWN_RADIUS: f32 = 10.0;
+/// How close to the ground counts as having landed (wherever that is).
+const TOUCHDOWN_HEIGHT: f32 = 2.0;
+/// How far off the ground a crow has to climb before it's done taking off.
+const TAKE_OFF_CLIMB: f32 = 30.0;
+/// The crow model has no idle animation, so perched crows hold still in
+/// the pose from the end of a flutter (just short of the last keyframe).
+const PERCHED_CLIP: &str = "Flutter";
+const PERCHED_POSE_AT: f32 = 0.8;
+/// How steeply a crow leaps up when it takes off, in radians.
+const TAKE_OFF_PITCH: f32 = 0.6;
+/// How far away we'll look for an obstacle to perch on.
+const PERCH_SEARCH_RADIUS: f32 = 150.0;
+/// How far ahead to land when there's nothing to perch on.
+const PERCH_GROUND_AHEAD: f32 = 60.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PerchPhase {
+    /// Flying down to the perch.
+    Descending,
+    /// Sat on it, resting.
+    Perched,
+    /// Flapping back up into the air.
+    TakingOff,
+}
+
+/// Where a crow is landing (or has landed), and a goal to get there.
+#[derive(Component, Debug)]
+pub struct Perch {
+    pub point: Vec3,
+    pub phase: PerchPhase,
+    pub target_vel: Vec3,
+    pub weight: f32,
+}
+
+impl Perch {
+    pub fn new(point: Vec3) -> Perch {
+        Perch {
+            point,
+            phase: PerchPhase::Descending,
+            target_vel: Vec3::ZERO,
+            weight: 0.0,
+        }
+    }
+}
+
+impl SteeringBehaviour for Perch {
+    fn steer(&self) -> Vec3 {
+        self.target_vel
+    }
+
+    fn weight(&self) -> f32 {
+        self.weight
+    }
+}
+
+/// Where to land: the top of the nearest obstacle below us within reach
+/// (and which one it is), or else the ground a little way ahead.
+pub fn choose_perch<T>(
+    position: Vec3,
+    forward: Vec3,
+    ground_at: impl Fn(f32, f32) -> f32,
+    tops: impl Iterator<Item=(T, Vec3)>,
+) -> (Vec3, Option<T>) {
+    let nearest = tops
+        .filter(|(_, top)| top.y < position.y && top.distance(position) <= PERCH_SEARCH_RADIUS)
+        .min_by(|a, b| a.1.distance_squared(position).total_cmp(&b.1.distance_squared(position)));
+    match nearest {
+        Some((thing, top)) => (top, Some(thing)),
+        None => {
+            let ahead = position + Vec3::new(forward.x, 0.0, forward.z).normalize_or_zero() * PERCH_GROUND_AHEAD;
+            (Vec3::new(ahead.x, ground_at(ahead.x, ahead.z), ahead.z), None)
+        }
+    }
+}
+
+/// The height of the ground at (x, z).
+fn ground_at(bounds: &Bounds, terrain: &Option<Res<Terrain>>, x: f32, z: f32) -> f32 {
+    match terrain {
+        Some(terrain) => terrain.height_at(x, z),
+        None => bounds.y_min,
+    }
+}
+
+/// Moves a spot on the ground to somewhere inside the walls of the arena.
+fn ground_inside(bounds: &Bounds, point: Vec3) -> Vec3 {
+    let centre = bounds.centre();
+    match bounds.shape {
+        ArenaShape::Box => Vec3::new(
+            point.x.clamp(bounds.x_min + bounds.margin, bounds.x_max - bounds.margin),
+            point.y,
+            point.z.clamp(bounds.z_min + bounds.margin, bounds.z_max - bounds.margin),
+        ),
+        // Only the very bottom of a sphere is on the ground.
+        ArenaShape::Sphere => Vec3::new(centre.x, point.y, centre.z),
+        ArenaShape::Cylinder => {
+            let offset = Vec3::new(point.x - centre.x, 0.0, point.z - centre.z);
+            let offset = offset.clamp_length_max((bounds.radius() - bounds.margin).max(0.0));
+            Vec3::new(centre.x + offset.x, point.y, centre.z + offset.z)
+        }
+    }
+}
+
+/// Flies landing crows down to their perch, and sets them down when they
+/// get there (or hit the ground on the way).
+pub fn perch_system(
+    mut commands: Commands,
+    mut query: Query<(Entity, &mut Perch, &mut Transform, &mut Flyer, Option<&mut Aerodynamics>)>,
+    bounds: Res<Bounds>,
+    terrain: Option<Res<Terrain>>,
+) {
+    for (entity, mut perch, mut transform, mut flyer, aero) in query.iter_mut() {
+        perch.target_vel = Vec3::ZERO;
+        perch.weight = 0.0;
+        if perch.phase != PerchPhase::Descending {
+            continue;
+        }
+
+        let position = transform.translation;
+        let ground = ground_at(&bounds, &terrain, position.x, position.z);
+        let touchdown = if position.distance(perch.point) < TOUCHDOWN_RADIUS {
+            Some(perch.point)
+        } else if position.y <= ground + TOUCHDOWN_HEIGHT {
+            Some(Vec3::new(position.x, ground, position.z))
+        } else {
+            None
+        };
+
+        match touchdown {
+            Some(point) => {
+                perch.point = point;
+                perch.phase = PerchPhase::Perched;
+                transform.translation = point;
+                transform.rotation = Quat::from_euler(EulerRot::YXZ, flyer.ang_y, 0.0, 0.0);
+                flyer.speed_linear = 0.0;
+                flyer.accel_linear = 0.0;
+                flyer.ang_x = 0.0;
+                flyer.ang_x_vel = 0.0;
+                flyer.ang_y_vel = 0.0;
+                if let Some(mut aero) = aero {
+                    aero.velocity = Vec3::ZERO;
+                }
+                commands.entity(entity).insert(Grounded);
+                commands.entity(entity).insert(StartAnim {
+                    name: String::from(PERCHED_CLIP),
+                    loop_plz: false,
+                    pose_at: Some(PERCHED_POSE_AT),
+                });
+            }
+            None => {
+                // As slowly as we can, so we can turn tightly onto it.
+                let vel = flyer.speed_linear * transform.forward();
+                let displacement = bounds.displacement(position, perch.point);
+                perch.target_vel = seek_velocity(displacement, flyer.props.spd_min) - vel;
+                perch.weight = 1.0;
+            }
+        }
+    }
+}
+
+/// Launches a perched crow up and away the way it's facing, flapping.
+fn take_off(
+    commands: &mut Commands,
+    entity: Entity,
+    perch: &mut Perch,
+    wingbeat: Option<&mut Wingbeat>,
+    flyer: &mut Flyer,
+    aero: Option<Mut<Aerodynamics>>,
+) {
+    perch.phase = PerchPhase::TakingOff;
+    if let Some(wingbeat) = wingbeat {
+        wingbeat.flapping = true;
+    }
+
+    flyer.ang_x = TAKE_OFF_PITCH;
+    let direction = Quat::from_euler(EulerRot::YXZ, flyer.ang_y, flyer.ang_x, 0.0) * -Vec3::Z;
+    flyer.speed_linear = flyer.props.spd_min;
+    flyer.goal_velocity = direction * flyer.props.spd_min;
+    if let Some(mut aero) = aero {
+        aero.velocity = direction * flyer.props.spd_min;
+    }
+
+    commands.entity(entity).remove::<Grounded>();
+    commands.entity(entity).insert(StartAnim {
+        name: String::from("Flap"),
+        loop_plz: true,
+        pose_at: None,
+    });
+}
+
+/// Whether a crow taking off has climbed far enough to be flying again.
+fn clear_of_perch(perch: &Perch, position: Vec3) -> bool {
+    perch.phase == PerchPhase::TakingOff && position.y >= perch.point.y + TAKE_OFF_CLIMB
+}
+
+/// Puts a crow back to flying normally, however its perching ended.
+fn stop_perching(commands: &mut Commands, entity: Entity, wingbeat: Option<&mut Wingbeat>, avoid: Option<&mut AvoidObstacles>) {
+    if let Some(avoid) = avoid {
+        avoid.ignore = None;
+    }
+    if let Some(wingbeat) = wingbeat {
+        wingbeat.flapping = false;
+    }
+    commands.entity(entity)
+        .remove::<Perch>()
+        .remove::<Grounded>()
+        .remove::<Foraging>();
+    commands.entity(entity).insert(StartAnim {
+        name: String::from("Soar"),
+        loop_plz: true,
+        pose_at: None,
+    });
+}
+
+/// Starts flying down to a perch.
+fn start_perching(commands: &mut Commands, entity: Entity, point: Vec3, wingbeat: Option<&mut Wingbeat>) {
+    if let Some(wingbeat) = wingbeat {
+        wingbeat.flapping = false;
+    }
+    commands.entity(entity).insert(Perch::new(point));
+    commands.entity(entity).insert(StartAnim {
+        name: String::from("Flutter"),
+        loop_plz: true,
+        pose_at: None,
+    });
+}
+
+/// Our landing action. Picks a perch, flies down to it, rests until we've
+/// got our stamina back and then takes off again.
+#[derive(Clone, Component, Debug)]
+pub struct Land {
+    pub take_off_above: f32,
+}
+
+pub fn land_action_system(
+    mut commands: Commands,
+    bounds: Res<Bounds>,
+    terrain: Option<Res<Terrain>>,
+    query_obstacles: Query<(Entity, &Transform, &Obstacle)>,
+    mut query_crows: Query<
+        (&mut Stamina, Option<&mut Wingbeat>, &mut Flyer, &Transform, Option<&mut AvoidObstacles>, Option<&mut Aerodynamics>, Option<&mut Perch>, Entity),
+        Without<Obstacle>,
+    >,
+    mut query_actor_stuff: Query<(&Actor, &mut ActionState, &Land)>,
+) {
+    for (Actor(actor), mut state, land) in query_actor_stuff.iter_mut() {
+        if let Ok((mut stamina, mut wingbeat, mut flyer, transform, mut avoid, aero, perch, entity)) = query_crows.get_mut(*actor) {
+            match *state {
+                ActionState::Requested => {
+                    let tops = query_obstacles.iter()
+                        .map(|(obstacle_entity, obstacle_transform, obstacle)| (obstacle_entity, obstacle.shape.top(obstacle_transform.translation)));
+                    let (point, obstacle) = choose_perch(
+                        transform.translation,
+                        transform.forward(),
+                        |x, z| ground_at(&bounds, &terrain, x, z),
+                        tops,
+                    );
+                    let point = match obstacle {
+                        Some(_) => point,
+                        None => {
+                            let inside = ground_inside(&bounds, point);
+                            Vec3::new(inside.x, ground_at(&bounds, &terrain, inside.x, inside.z), inside.z)
+                        }
+                    };
+                    if let Some(avoid) = avoid.as_deref_mut() {
+                        avoid.ignore = obstacle;
+                    }
+                    start_perching(&mut commands, entity, point, wingbeat.as_deref_mut());
+                    *state = ActionState::Executing;
+                }
+                ActionState::Executing => {
+                    let mut perch = match perch {
+                        Some(perch) => perch,
+                        None => continue,
+                    };
+                    match perch.phase {
+                        PerchPhase::Descending => {}
+                        PerchPhase::Perched => {
+                            if stamina.current_value >= land.take_off_above {
+                                take_off(&mut commands, entity, &mut perch, wingbeat.as_deref_mut(), &mut flyer, aero);
+                            }
+                        }
+                        PerchPhase::TakingOff => {
+                            if clear_of_perch(&perch, transform.translation) {
+                                *state = ActionState::Success;
+                                stamina.average = stamina.current_value;
+                                stop_perching(&mut commands, entity, wingbeat.as_deref_mut(), avoid.as_deref_mut());
+                            }
+                        }
+                    }
+                }
+                ActionState::Cancelled => {
+                    *state = ActionState::Failure;
+                    stop_perching(&mut commands, entity, wingbeat.as_deref_mut(), avoid.as_deref_mut());
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// The state of being tired enough to land. Once we've started landing
+/// we want to see it through.
+#[derive(Clone, Component, Debug)]
+pub struct LandScorer {
+    pub land_below: f32,
+}
+
+pub fn land_scorer_system(
+    query_components: Query<(&Stamina, Option<&Perch>, Option<&Foraging>)>,
+    mut query_actor_stuff: Query<(&Actor, &mut Score, &LandScorer)>,
+) {
+    for (Actor(actor), mut score, scorer) in query_actor_stuff.iter_mut() {
+        if let Ok((stamina, perch, foraging)) = query_components.get(*actor) {
+            let tired = jay_math::inv_lerp(scorer.land_below + 10.0, scorer.land_below, stamina.average).clamp(0.0, 1.0);
+            score.set(match (perch, foraging) {
+                (Some(_), None) => 1.0,
+                _ => tired,
+            });
+        }
+    }
+}
+
+/// How close to food a perched crow has to be to eat it.
+const EAT_RADIUS: f32 = 15.0;
+/// The least food which is worth flying to.
+const FOOD_WORTH_EATING: f32 = 10.0;
+
+/// How hungry a crow is, from 0 (full) to 100 (starving).
+#[derive(Component, Debug)]
+pub struct Hunger {
+    gain_per_second: f32,
+    eat_per_second: f32,
+    current_value: f32,
+}
+
+impl Hunger {
+    pub fn new(config: &HungerConfig) -> Hunger {
+        Hunger {
+            gain_per_second: config.gain_per_second,
+            eat_per_second: config.eat_per_second,
+            current_value: config.initial,
+        }
+    }
+
+    pub fn current_value(&self) -> f32 {
+        self.current_value
+    }
+}
+
+/// The food a foraging crow is after.
+#[derive(Component, Debug)]
+pub struct Foraging {
+    pub food: Entity,
+}
+
+/// Whether a crow at the given position is close enough to eat food there.
+fn within_reach(bounds: &Bounds, position: Vec3, food: Vec3) -> bool {
+    let displacement = bounds.displacement(position, food);
+    Vec3::new(displacement.x, 0.0, displacement.z).length() <= EAT_RADIUS
+}
+
+/// Makes crows hungrier, and lets foraging crows which have landed by
+/// their food eat it.
+pub fn hunger_system(
+    time: Res<SimulationTime>,
+    bounds: Res<Bounds>,
+    mut query: Query<(&mut Hunger, &Transform, Option<&Foraging>, Option<&Grounded>)>,
+    mut query_food: Query<(&mut FoodSource, &Transform)>,
+) {
+    for (mut hunger, transform, foraging, grounded) in query.iter_mut() {
+        hunger.current_value = (hunger.current_value + hunger.gain_per_second * time.delta_seconds()).min(100.0);
+
+        let foraging = match (foraging, grounded) {
+            (Some(foraging), Some(_)) => foraging,
+            _ => continue,
+        };
+        if let Ok((mut food, food_transform)) = query_food.get_mut(foraging.food) {
+            if within_reach(&bounds, transform.translation, food_transform.translation) {
+                let wanted = (hunger.eat_per_second * time.delta_seconds()).min(hunger.current_value);
+                hunger.current_value -= food.eat(wanted);
+            }
+        }
+    }
+}
+
+/// The nearest food worth flying to (going by `displacement`, the way to
+/// it from us), if there is any.
+pub fn nearest_food<T>(displacement: impl Fn(Vec3) -> Vec3, food: impl Iterator<Item=(T, Vec3, f32)>) -> Option<(T, Vec3)> {
+    food
+        .filter(|(_, _, amount)| *amount >= FOOD_WORTH_EATING)
+        .map(|(thing, at, _)| (thing, at, displacement(at).length_squared()))
+        .min_by(|a, b| a.2.total_cmp(&b.2))
+        .map(|(thing, at, _)| (thing, at))
+}
+
+/// Our foraging action. Flies to the nearest food, lands by it and eats
+/// until we're full (or it's all gone), then takes off again.
+#[derive(Clone, Component, Debug)]
+pub struct Forage {
+    pub sated_below: f32,
+}
+
+pub fn forage_action_system(
+    mut commands: Commands,
+    bounds: Res<Bounds>,
+    query_food: Query<(Entity, &Transform, &FoodSource)>,
+    mut query_crows: Query<
+        (&Hunger, Option<&mut Wingbeat>, &mut Flyer, &Transform, Option<&mut AvoidObstacles>, Option<&mut Aerodynamics>, Option<&mut Perch>, Option<&Foraging>, Entity),
+        Without<FoodSource>,
+    >,
+    mut query_actor_stuff: Query<(&Actor, &mut ActionState, &Forage)>,
+) {
+    for (Actor(actor), mut state, forage) in query_actor_stuff.iter_mut() {
+        if let Ok((hunger, mut wingbeat, mut flyer, transform, mut avoid, aero, perch, foraging, entity)) = query_crows.get_mut(*actor) {
+            match *state {
+                ActionState::Requested => {
+                    let position = transform.translation;
+                    let food = query_food.iter()
+                        .map(|(food_entity, food_transform, food)| (food_entity, food_transform.translation, food.amount));
+                    match nearest_food(|at| bounds.displacement(position, at), food) {
+                        Some((food, point)) => {
+                            if let Some(avoid) = avoid.as_deref_mut() {
+                                avoid.ignore = None;
+                            }
+                            start_perching(&mut commands, entity, point, wingbeat.as_deref_mut());
+                            commands.entity(entity).insert(Foraging { food });
+                            *state = ActionState::Executing;
+                        }
+                        None => *state = ActionState::Failure,
+                    }
+                }
+                ActionState::Executing => {
+                    let (mut perch, foraging) = match (perch, foraging) {
+                        (Some(perch), Some(foraging)) => (perch, foraging),
+                        _ => continue,
+                    };
+                    match perch.phase {
+                        PerchPhase::Descending => {}
+                        PerchPhase::Perched => {
+                            // Missed it, or had our fill.
+                            let food_left = query_food.get(foraging.food).ok()
+                                .filter(|(_, food_transform, food)| {
+                                    food.amount > 0.0 && within_reach(&bounds, transform.translation, food_transform.translation)
+                                });
+                            if food_left.is_none() || hunger.current_value <= forage.sated_below {
+                                take_off(&mut commands, entity, &mut perch, wingbeat.as_deref_mut(), &mut flyer, aero);
+                            }
+                        }
+                        PerchPhase::TakingOff => {
+                            if clear_of_perch(&perch, transform.translation) {
+                                *state = if hunger.current_value <= forage.sated_below { ActionState::Success } else { ActionState::Failure };
+                                stop_perching(&mut commands, entity, wingbeat.as_deref_mut(), avoid.as_deref_mut());
+                            }
+                        }
+                    }
+                }
+                ActionState::Cancelled => {
+                    *state = ActionState::Failure;
+                    stop_perching(&mut commands, entity, wingbeat.as_deref_mut(), avoid.as_deref_mut());
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// The state of being hungry enough to go and find food. Hunger is
+/// weighed against stamina, as it's no good setting off exhausted (better
+/// to land and rest first). Once we've started foraging we see it through.
+#[derive(Clone, Component, Debug)]
+pub struct ForageScorer {
+    pub hungry_above: f32,
+}
+
+pub fn forage_scorer_system(
+    query_food: Query<&FoodSource>,
+    query_components: Query<(&Hunger, &Stamina, Option<&Perch>, Option<&Foraging>)>,
+    mut query_actor_stuff: Query<(&Actor, &mut Score, &ForageScorer)>,
+) {
+    let food_about = query_food.iter().any(|food| food.amount >= FOOD_WORTH_EATING);
+    for (Actor(actor), mut score, scorer) in query_actor_stuff.iter_mut() {
+        if let Ok((hunger, stamina, perch, foraging)) = query_components.get(*actor) {
+            let hunger_factor = jay_math::inv_lerp(scorer.hungry_above - 20.0, scorer.hungry_above + 20.0, hunger.current_value).clamp(0.0, 1.0);
+            let stamina_factor = jay_math::inv_lerp(10.0, 40.0, stamina.current_value).clamp(0.0, 1.0);
+            score.set(match (perch, foraging) {
+                (_, Some(_)) => 1.0,
+                // Resting somewhere else.
+                (Some(_), None) => 0.0,
+                (None, None) if food_about => hunger_factor * stamina_factor,
+                (None, None) => 0.0,
+            });
+        }
+    }
+}
+
+/// A simple goal to keep the crows relatively level, in lieu of an actual
+/// gravity/lift model. Crows with `Aerodynamics` have one, so they hardly
+/// need it.
 #[derive(Component, Debug)]
 pub struct KeepLevel {
     pub target_vel: Vec3,
     pub weight: f32,
 }
 
+impl SteeringBehaviour for KeepLevel {
+    fn steer(&self) -> Vec3 {
+        self.target_vel
+    }
+
+    fn weight(&self) -> f32 {
+        self.weight
+    }
+}
+
 /// The system which updates the target velocity for KeepLevel.
 pub fn keep_level_system(
     mut query: Query<(&Transform, &Flyer, &mut KeepLevel)>,
@@ -133,34 +895,69 @@ pub fn keep_level_system(
 pub struct KeepInBounds {
     pub target_vel: Vec3,
     pub weight: f32,
+    /// Whether we've sunk below the floor, and have to climb back up
+    /// whether we're flapping or not (see `Wingbeat::apply`).
+    pub below_floor: bool,
+}
+
+impl SteeringBehaviour for KeepInBounds {
+    fn steer(&self) -> Vec3 {
+        self.target_vel
+    }
+
+    fn weight(&self) -> f32 {
+        self.weight
+    }
 }
 
+/// How far ahead to look for rising ground, in seconds.
+const TERRAIN_LOOK_AHEAD_SECS: f32 = 1.0;
+
 /// The system which updates the target and weight on KeepInBounds.
+/// With a `Terrain`, the floor follows the ground (just below us or a
+/// little ahead, whichever is higher) rather than sitting at `y_min`.
+/// Sphere and cylinder arenas steer away from their curved wall, and
+/// wrapped sides aren't steered away from at all.
 pub fn keep_in_bounds_system(
-    mut query: Query<(&Transform, &Flyer, &mut KeepInBounds)>,
+    mut query: Query<(&Transform, &Flyer, &mut KeepInBounds, Option<&Perch>)>,
     bounds: Res<Bounds>,
+    terrain: Option<Res<Terrain>>,
 )
 {
-    for (transform, flyer, mut keep_in_bounds) in query.iter_mut() {
+    for (transform, flyer, mut keep_in_bounds, perch) in query.iter_mut() {
+        // Let landing crows get down to the ground.
+        let landing = perch.map_or(false, |perch| perch.phase == PerchPhase::Descending);
         let vel = flyer.speed_linear * transform.forward();
+        let y_min = match &terrain {
+            Some(terrain) => {
+                let ahead = transform.translation + vel * TERRAIN_LOOK_AHEAD_SECS;
+                terrain.floor_at(transform.translation.x, transform.translation.z)
+                    .max(terrain.floor_at(ahead.x, ahead.z))
+                    .max(bounds.y_min)
+            }
+            None => bounds.y_min,
+        };
 
         let max = flyer.props.spd_max;
 
         keep_in_bounds.target_vel = Vec3::ZERO;
         keep_in_bounds.weight = 0.0;
+        keep_in_bounds.below_floor = !landing && transform.translation.y < y_min;
 
-        if transform.translation.x < bounds.x_min + bounds.margin {
+        let box_sides = bounds.shape == ArenaShape::Box && !bounds.wraps();
+
+        if box_sides && transform.translation.x < bounds.x_min + bounds.margin {
             let t = jay_math::inv_lerp(bounds.x_min + bounds.margin, bounds.x_min, transform.translation.x);
             keep_in_bounds.target_vel.x += max * t;
             keep_in_bounds.weight = 1.0;
         }
-        if transform.translation.x > bounds.x_max - bounds.margin {
+        if box_sides && transform.translation.x > bounds.x_max - bounds.margin {
             let t = jay_math::inv_lerp(bounds.x_max - bounds.margin, bounds.x_max, transform.translation.x);
             keep_in_bounds.target_vel.x -= max * t;
             keep_in_bounds.weight = 1.0;
         }
-        if transform.translation.y < bounds.y_min + bounds.margin {
-            let t = jay_math::inv_lerp(bounds.y_min + bounds.margin, bounds.y_min, transform.translation.y);
+        if !landing && transform.translation.y < y_min + bounds.margin {
+            let t = jay_math::inv_lerp(y_min + bounds.margin, y_min, transform.translation.y);
             keep_in_bounds.target_vel.y += max * t;
             keep_in_bounds.weight = 1.0;
         }
@@ -169,91 +966,97 @@ pub fn keep_in_bounds_system(
             keep_in_bounds.target_vel.y -= max * t;
             keep_in_bounds.weight = 1.0;
         }
-        if transform.translation.z < bounds.z_min + bounds.margin {
+        if box_sides && transform.translation.z < bounds.z_min + bounds.margin {
             let t = jay_math::inv_lerp(bounds.z_min + bounds.margin, bounds.z_min, transform.translation.z);
             keep_in_bounds.target_vel.z += max * t;
             keep_in_bounds.weight = 1.0;
         }
-        if transform.translation.z > bounds.z_max - bounds.margin {
+        if box_sides && transform.translation.z > bounds.z_max - bounds.margin {
             let t = jay_math::inv_lerp(bounds.z_max - bounds.margin, bounds.z_max, transform.translation.z);
             keep_in_bounds.target_vel.z -= max * t;
             keep_in_bounds.weight = 1.0;
         }
+        if bounds.shape != ArenaShape::Box {
+            let (inside, out) = bounds.wall_distance(transform.translation);
+            if inside < bounds.margin {
+                let t = jay_math::inv_lerp(bounds.margin, 0.0, inside);
+                keep_in_bounds.target_vel -= out * max * t;
+                keep_in_bounds.weight = 1.0;
+            }
+        }
 
         keep_in_bounds.target_vel = keep_in_bounds.target_vel - vel;
     }
 }
 
-/// Converts various goals (including boids and others) to the goal velocity
-/// for a "flyer".
-pub fn flyer_goal_velocity_from_boids_system(
-    time: Res<Time>,
-    common_props: Res<CrowGlobalProps>,
-    mut query: Query<(&mut Flyer, &Separation, &Alignment, &Cohesion, &KeepInBounds, &KeepLevel)>,
-)
-{
-    for (mut flyer, separation, alignment, cohesion, keep_in_bounds, keep_level) in query.iter_mut() {
-
-        // Add up the goals.
-        flyer.goal_velocity += time.delta().as_secs_f32() *
-            (separation.separation_factor * separation.weight * common_props.separation_weight
-                + alignment.alignment_factor * alignment.weight * common_props.alignment_weight
-                + cohesion.cohesion_factor * cohesion.weight * common_props.cohesion_weight
-                + keep_in_bounds.target_vel * keep_in_bounds.weight * common_props.keep_in_bounds_weight
-                + keep_level.target_vel * keep_level.weight * common_props.keep_level_weight
-            );
-
-        // Clamp our goal velocity within our properties.
-        let mag_sqr = flyer.goal_velocity.length_squared();
-        if mag_sqr > (flyer.props.spd_max * flyer.props.spd_max)
-        {
-            let vel_n = flyer.goal_velocity.normalize();
-            flyer.goal_velocity = vel_n * flyer.props.spd_max;
-        } else if mag_sqr < (flyer.props.spd_min * flyer.props.spd_min)
-        {
-            let vel_n = flyer.goal_velocity.normalize();
-            flyer.goal_velocity = vel_n * flyer.props.spd_min;
-        }
+/// Spawns all of a species at random positions in its spawn region.
+/// Pass `None` for the model to spawn them without any visuals (e.g.
+/// when running headless).
+pub fn spawn_species(
+    commands: &mut Commands,
+    rng: &mut impl Rng,
+    bounds: &Bounds,
+    id: Species,
+    species: &SpeciesConfig,
+    model: Option<Handle<Gltf>>,
+) {
+    let (min, max) = species.spawn_box(bounds);
+
+    for _ in 0..species.count
+    {
+        let x = rng.gen_range(min[0]..=max[0]);
+        let y = rng.gen_range(min[1]..=max[1]);
+        let z = rng.gen_range(min[2]..=max[2]);
+
+        let rot = rng.gen_range(-TAU..TAU) * 0.5;
+
+        let pos = Vec3::from((x, y, z));
+
+        make_instance(
+            commands,
+            id,
+            species,
+            model.clone(),
+            pos,
+            Quat::from_axis_angle(Vec3::Y, rot),
+        );
     }
 }
 
-/// Makes a single crow instance.
+/// Makes a single crow instance. The model is optional so that the
+/// simulation can run without any assets loaded.
 pub fn make_instance(
     commands: &mut Commands,
-    asset_server: &Res<AssetServer>,
-    model_filename: &str,
+    id: Species,
+    species: &SpeciesConfig,
+    model: Option<Handle<Gltf>>,
     position: Vec3,
     rotation: Quat,
-) {
-    let spd = 75.0;
+) -> Entity {
+    let spd = species.initial_speed;
     let fwd = rotation * -Vec3::Z;
     let (yaw, pitch) = jay_math::vec3_to_yaw_pitch(fwd);
 
-    let gltf = asset_server.load(model_filename);
-    commands.spawn_bundle((
-        ModelGLTF {
-            handle: gltf,
-        },
-        ModelWaitingToSpawn {},
-        StartAnim {
-            name: String::from("Soar"),
-            loop_plz: true,
-        },
-        Name::new(format!("House Crow")),
+    let mut crow = commands.spawn_bundle((
+        Name::new(species.name.clone()),
         Observer {
+            perception_radius: species.observer.perception_radius,
+            fov_half_angle: species.observer.fov_half_angle,
+            max_neighbours: species.observer.max_neighbours,
             ..Default::default()
         },
         Separation {
-            separation_factor: Vec3::ZERO,
-            weight: 0.10,
+            weight: species.boids.separation,
+            // The radius and falloff are set from the global props once spawned.
+            ..Default::default()
         },
         Alignment {
             alignment_factor: Vec3::ZERO,
-            weight: 0.20,
+            weight: species.boids.alignment,
         },
         Cohesion {
             cohesion_factor: Vec3::ZERO,
-            weight: 0.02,
+            weight: species.boids.cohesion,
         },
         Flyer {
             speed_linear: spd,
@@ -263,14 +1066,7 @@ pub fn make_instance(
             ang_x_vel: 0.0,
             ang_y_vel: 0.0,
             goal_velocity: fwd * spd,
-            props: FlyerProps {
-                accel_max: 3.0,
-                spd_min: 50.0,
-                spd_max: 100.0,
-                ang_spd_x_max: 0.7,
-                ang_spd_y_max: 3.0,
-                ang_z_from_y_spd: 0.33,
-            },
+            props: species.flight.to_props(),
             ..Default::default()
         },
         Velocitator {
@@ -280,32 +1076,237 @@ pub fn make_instance(
         {
             target_vel: Vec3::ZERO,
             weight: 0.0,
+            below_floor: false,
         },
         KeepLevel
         {
             target_vel: Vec3::ZERO,
-            weight: 0.1,
+            weight: species.boids.keep_level,
         },
+        Flee
+        {
+            target_vel: Vec3::ZERO,
+            weight: 0.0,
+            radius: species.boids.flee_radius,
+        },
+        AvoidObstacles
+        {
+            target_vel: Vec3::ZERO,
+            weight: 0.0,
+            look_ahead_secs: species.boids.obstacle_look_ahead_secs,
+            clearance: species.boids.obstacle_clearance,
+            ignore: None,
+        },
+        SteeringAccumulator::default(),
         Stamina
         {
-            recover_per_second: 10.0,
-            current_value: 70.0,
+            recover_per_second: species.stamina.recover_per_second,
+            rest_per_second: species.stamina.rest_per_second,
+            flap_usage_per_second: species.stamina.flap_usage_per_second,
+            current_value: species.stamina.initial,
+            average: species.stamina.initial,
         },
         Thinker::build()
             .picker(FirstToScore { threshold: 0.6 })
             .when(
-                FlapScorer,
-                Flap {
-                    stamina_usage_per_sec: 30.0,
+                ForageScorer {
+                    hungry_above: species.hunger.hungry_above,
+                },
+                Forage {
+                    sated_below: species.hunger.sated_below,
+                },
+            )
+            .when(
+                LandScorer {
+                    land_below: species.stamina.land_below,
+                },
+                Land {
+                    take_off_above: species.stamina.take_off_above,
                 },
-            ),
-    )).insert_bundle(
+            )
+            .when(FlapScorer, Flap),
+    ));
+    let transform = Transform {
+        translation: position,
+        rotation,
+        scale: Vec3::ONE * species.scale,
+    };
+    crow.insert_bundle(
         SpatialBundle {
-            transform: Transform {
-                translation: position,
-                rotation,
-                scale: Vec3::ONE * 0.1,
-            },
+            transform,
             ..Default::default()
-        });
+        })
+        .insert(Interpolated::new(transform))
+        .insert(Wingbeat::new(species.flight.accel_max, &species.wingbeat))
+        .insert(Hunger::new(&species.hunger))
+        .insert(id);
+
+    if let Some(aerodynamics) = &species.aerodynamics {
+        crow.insert(aerodynamics.to_aerodynamics(fwd * spd));
+    }
+
+    match &species.goal {
+        Some(GoalConfig::Seek { point }) => {
+            crow.insert(Seek::new(NavTarget::Point(Vec3::from(*point))));
+        }
+        Some(GoalConfig::Arrive { point, slowing_radius }) => {
+            crow.insert(Arrive::new(NavTarget::Point(Vec3::from(*point)), *slowing_radius));
+        }
+        Some(GoalConfig::Patrol { waypoints, reach_radius, looping }) => {
+            crow.insert(Patrol::new(waypoints.iter().map(|w| Vec3::from(*w)).collect(), *reach_radius, *looping));
+        }
+        None => {}
+    }
+
+    if let Some(handle) = model {
+        crow.insert_bundle((
+            ModelGLTF {
+                handle,
+            },
+            ModelWaitingToSpawn {},
+            StartAnim {
+                name: String::from("Soar"),
+                loop_plz: true,
+                pose_at: None,
+            },
+        ));
+    }
+
+    crow.id()
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::schedule::IntoSystemDescriptor;
+    use super::*;
+
+    #[test]
+    fn perch_on_nearest_obstacle_below() {
+        let tops = [(1, Vec3::new(100.0, 20.0, 0.0)), (2, Vec3::new(30.0, 20.0, 0.0)), (3, Vec3::new(10.0, 80.0, 0.0))];
+        let (point, obstacle) = choose_perch(Vec3::new(0.0, 50.0, 0.0), -Vec3::Z, |_, _| 0.0, tops.into_iter());
+        assert_eq!(obstacle, Some(2));
+        assert_eq!(point, Vec3::new(30.0, 20.0, 0.0));
+    }
+
+    #[test]
+    fn forage_at_nearest_food_worth_eating() {
+        let position = Vec3::new(0.0, 50.0, 0.0);
+        let food = [(1, Vec3::new(20.0, 0.0, 0.0), 5.0), (2, Vec3::new(0.0, 0.0, 60.0), 100.0), (3, Vec3::new(-90.0, 0.0, 0.0), 100.0)];
+        assert_eq!(nearest_food(|at| at - position, food.into_iter()), Some((2, Vec3::new(0.0, 0.0, 60.0))));
+        assert_eq!(nearest_food(|at| at - position, food[..1].iter().copied()), None);
+    }
+
+    #[test]
+    fn perch_on_ground_ahead() {
+        let tops = [(1, Vec3::new(500.0, 20.0, 0.0))];
+        let (point, obstacle) = choose_perch(Vec3::new(0.0, 50.0, 0.0), Vec3::new(0.0, -0.6, -0.8), |x, _| x + 5.0, tops.into_iter());
+        assert_eq!(obstacle, None);
+        assert!((point - Vec3::new(0.0, 5.0, -PERCH_GROUND_AHEAD)).length() < 1e-4);
+    }
+
+    #[test]
+    fn soaring_below_the_floor_can_climb() {
+        let wingbeat = Wingbeat::new(20.0, &WingbeatConfig::default());
+        let mut props = FlyerProps::default();
+        let up = Vec3::new(0.0, 0.6, -0.8);
+
+        wingbeat.apply(&mut props, false);
+        assert!(limit_climb(up, 50.0, props.climb_max).y <= 0.0);
+        assert!(props.sink_rate > 0.0);
+
+        wingbeat.apply(&mut props, true);
+        assert!(limit_climb(up, 50.0, props.climb_max).y > 0.0);
+        assert_eq!(props.sink_rate, 0.0);
+    }
+
+    fn perching_world(phase: PerchPhase, stamina: f32, state: ActionState) -> (World, Entity, Entity) {
+        let mut world = World::new();
+        world.insert_resource(Bounds::new(50.0, 0.0, 500.0, 0.0, 250.0, 0.0, 500.0, 20.0));
+
+        let point = Vec3::new(100.0, 0.0, 100.0);
+        let mut perch = Perch::new(point);
+        perch.phase = phase;
+        let crow = world.spawn()
+            .insert_bundle((
+                Transform::from_translation(point + Vec3::Y),
+                Flyer::default(),
+                Wingbeat::new(20.0, &WingbeatConfig::default()),
+                Stamina {
+                    recover_per_second: 0.0,
+                    rest_per_second: 0.0,
+                    flap_usage_per_second: 0.0,
+                    current_value: stamina,
+                    average: stamina,
+                },
+                AvoidObstacles {
+                    target_vel: Vec3::ZERO,
+                    weight: 0.0,
+                    look_ahead_secs: 1.0,
+                    clearance: 10.0,
+                    ignore: None,
+                },
+                perch,
+            ))
+            .id();
+        let action = world.spawn()
+            .insert_bundle((Actor(crow), state, Land { take_off_above: 80.0 }))
+            .id();
+        (world, crow, action)
+    }
+
+    fn run_system<Params>(world: &mut World, system: impl IntoSystemDescriptor<Params>) {
+        SystemStage::single_threaded().with_system(system).run(world);
+    }
+
+    #[test]
+    fn late_crows_get_the_current_separation() {
+        let mut world = World::new();
+        world.insert_resource(CrowGlobalProps { separation_radius: 20.0, ..Default::default() });
+        let mut stage = SystemStage::single_threaded().with_system(separation_settings_system);
+        stage.run(&mut world);
+
+        let crow = world.spawn().insert(Separation::default()).id();
+        stage.run(&mut world);
+        assert_eq!(world.get::<Separation>(crow).unwrap().radius, 20.0);
+    }
+
+    #[test]
+    fn lands_rests_and_takes_off() {
+        let (mut world, crow, action) = perching_world(PerchPhase::Descending, 50.0, ActionState::Executing);
+
+        run_system(&mut world, perch_system);
+        assert_eq!(world.get::<Perch>(crow).unwrap().phase, PerchPhase::Perched);
+        assert!(world.get::<Grounded>(crow).is_some());
+
+        // Still tired.
+        run_system(&mut world, land_action_system);
+        assert_eq!(world.get::<Perch>(crow).unwrap().phase, PerchPhase::Perched);
+
+        world.get_mut::<Stamina>(crow).unwrap().current_value = 90.0;
+        run_system(&mut world, land_action_system);
+        assert_eq!(world.get::<Perch>(crow).unwrap().phase, PerchPhase::TakingOff);
+        assert!(world.get::<Grounded>(crow).is_none());
+        assert!(world.get::<Wingbeat>(crow).unwrap().flapping);
+
+        // Not clear of the perch yet.
+        run_system(&mut world, land_action_system);
+        assert_eq!(world.get::<ActionState>(action), Some(&ActionState::Executing));
+
+        world.get_mut::<Transform>(crow).unwrap().translation.y = TAKE_OFF_CLIMB;
+        run_system(&mut world, land_action_system);
+        assert_eq!(world.get::<ActionState>(action), Some(&ActionState::Success));
+        assert!(world.get::<Perch>(crow).is_none());
+        assert!(!world.get::<Wingbeat>(crow).unwrap().flapping);
+    }
+
+    #[test]
+    fn cancelled_landing_stops_perching() {
+        let (mut world, crow, action) = perching_world(PerchPhase::Perched, 50.0, ActionState::Cancelled);
+        world.entity_mut(crow).insert(Grounded).remove::<AvoidObstacles>();
+
+        run_system(&mut world, land_action_system);
+        assert_eq!(world.get::<ActionState>(action), Some(&ActionState::Failure));
+        assert!(world.get::<Perch>(crow).is_none());
+        assert!(world.get::<Grounded>(crow).is_none());
+    }
 }
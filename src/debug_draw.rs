@@ -0,0 +1,295 @@
+use bevy::{
+    prelude::*,
+    render::{
+        mesh::PrimitiveTopology,
+        view::RenderLayers,
+    },
+};
+use crate::boids::{Alignment, Cohesion, Separation};
+use crate::crows::{KeepInBounds, KeepLevel};
+use crate::flight::{Flyer, Grounded};
+use crate::observe::{Observer, StuffsToObserve};
+use crate::simulation::SimulationLabel;
+use crate::steering::SteeringBehaviour;
+use crate::velocitator::Velocitator;
+
+/// The render layer for debug lines which only make sense from above (the
+/// spatial hash's cells). Give it to the top-down camera to see them.
+pub const TOP_DOWN_LAYER: u8 = 1;
+
+/// The debug draw plugin. Draws lines showing why flyers are doing what
+/// they're doing: their steering, who they can see and where they'd like
+/// to go. Toggle it with F3, or with `DebugDrawSettings`.
+pub struct DebugDraw;
+
+impl Plugin for DebugDraw {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<DebugDrawSettings>()
+            .add_startup_system(debug_lines_startup)
+            .add_system(debug_draw_toggle_system)
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                debug_draw_system.after(SimulationLabel::Interpolate),
+            );
+    }
+}
+
+/// One kind of thing we can draw, each in its own colour.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DebugLayer {
+    Separation,
+    Alignment,
+    Cohesion,
+    KeepInBounds,
+    KeepLevel,
+    Neighbours,
+    GoalVelocity,
+    Velocity,
+    /// The occupied `StuffsToObserve` cells, on the `TOP_DOWN_LAYER`.
+    Cells,
+}
+
+impl DebugLayer {
+    pub const ALL: [DebugLayer; 9] = [
+        DebugLayer::Separation,
+        DebugLayer::Alignment,
+        DebugLayer::Cohesion,
+        DebugLayer::KeepInBounds,
+        DebugLayer::KeepLevel,
+        DebugLayer::Neighbours,
+        DebugLayer::GoalVelocity,
+        DebugLayer::Velocity,
+        DebugLayer::Cells,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            DebugLayer::Separation => "separation",
+            DebugLayer::Alignment => "alignment",
+            DebugLayer::Cohesion => "cohesion",
+            DebugLayer::KeepInBounds => "keep in bounds",
+            DebugLayer::KeepLevel => "keep level",
+            DebugLayer::Neighbours => "neighbours",
+            DebugLayer::GoalVelocity => "goal velocity",
+            DebugLayer::Velocity => "velocity",
+            DebugLayer::Cells => "cells",
+        }
+    }
+
+    pub fn color(self) -> Color {
+        match self {
+            DebugLayer::Separation => Color::RED,
+            DebugLayer::Alignment => Color::GREEN,
+            DebugLayer::Cohesion => Color::BLUE,
+            DebugLayer::KeepInBounds => Color::YELLOW,
+            DebugLayer::KeepLevel => Color::CYAN,
+            DebugLayer::Neighbours => Color::GRAY,
+            DebugLayer::GoalVelocity => Color::FUCHSIA,
+            DebugLayer::Velocity => Color::WHITE,
+            DebugLayer::Cells => Color::ORANGE,
+        }
+    }
+}
+
+pub struct DebugDrawSettings {
+    pub enabled: bool,
+    shown: [bool; DebugLayer::ALL.len()],
+    /// How long to draw the steering vectors, per unit.
+    pub steering_scale: f32,
+    /// How long to draw the velocities, per unit.
+    pub velocity_scale: f32,
+}
+
+impl Default for DebugDrawSettings {
+    fn default() -> Self {
+        DebugDrawSettings {
+            enabled: false,
+            shown: [true; DebugLayer::ALL.len()],
+            steering_scale: 0.5,
+            velocity_scale: 0.25,
+        }
+    }
+}
+
+impl DebugDrawSettings {
+    pub fn is_shown(&self, layer: DebugLayer) -> bool {
+        self.enabled && self.shown[layer as usize]
+    }
+
+    /// Whether a layer is shown when debug drawing is enabled (e.g. for a
+    /// checkbox).
+    pub fn shown_mut(&mut self, layer: DebugLayer) -> &mut bool {
+        &mut self.shown[layer as usize]
+    }
+}
+
+/// The lines for one layer, drawn as a single line list mesh.
+#[derive(Component)]
+struct DebugLines(DebugLayer);
+
+/// A line list mesh through the given pairs of points. Lines don't need
+/// normals or UVs, but the PBR pipeline wants them all the same.
+fn line_mesh(mut points: Vec<[f32; 3]>) -> Mesh {
+    if points.is_empty() {
+        points = vec![[0.0; 3]; 2];
+    }
+    let mut mesh = Mesh::new(PrimitiveTopology::LineList);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, vec![[0.0, 1.0, 0.0]; points.len()]);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, vec![[0.0, 0.0]; points.len()]);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, points);
+    mesh
+}
+
+/// The edges of a cell as seen from above: a square halfway up it.
+fn cell_outline(min: Vec3, max: Vec3) -> [Vec3; 8] {
+    let y = 0.5 * (min.y + max.y);
+    let corners = [
+        Vec3::new(min.x, y, min.z),
+        Vec3::new(max.x, y, min.z),
+        Vec3::new(max.x, y, max.z),
+        Vec3::new(min.x, y, max.z),
+    ];
+    [
+        corners[0], corners[1],
+        corners[1], corners[2],
+        corners[2], corners[3],
+        corners[3], corners[0],
+    ]
+}
+
+fn debug_lines_startup(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    for layer in DebugLayer::ALL {
+        let mut lines = commands.spawn_bundle(PbrBundle {
+            mesh: meshes.add(line_mesh(Vec::new())),
+            material: materials.add(StandardMaterial {
+                base_color: layer.color(),
+                unlit: true,
+                ..default()
+            }),
+            visibility: Visibility { is_visible: false },
+            ..default()
+        });
+        lines.insert_bundle((DebugLines(layer), Name::new(format!("Debug {}", layer.name()))));
+        if layer == DebugLayer::Cells {
+            lines.insert(RenderLayers::layer(TOP_DOWN_LAYER));
+        }
+    }
+}
+
+fn debug_draw_toggle_system(keys: Res<Input<KeyCode>>, mut settings: ResMut<DebugDrawSettings>) {
+    if keys.just_pressed(KeyCode::F3) {
+        settings.enabled = !settings.enabled;
+    }
+}
+
+/// Adds a line along a steering behaviour's (weighted) contribution.
+fn push_steering<T: SteeringBehaviour>(points: &mut Vec<[f32; 3]>, position: Vec3, behaviour: Option<&T>, scale: f32) {
+    if let Some(behaviour) = behaviour {
+        let steer = behaviour.steer() * behaviour.weight();
+        if steer != Vec3::ZERO {
+            points.push(position.to_array());
+            points.push((position + steer * scale).to_array());
+        }
+    }
+}
+
+/// Rebuilds the line meshes for every layer being shown, and hides the rest.
+fn debug_draw_system(
+    settings: Res<DebugDrawSettings>,
+    stuff_to_observe: Option<Res<StuffsToObserve>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut query_lines: Query<(&DebugLines, &Handle<Mesh>, &mut Visibility)>,
+    query_flyers: Query<(
+        &Transform,
+        &Flyer,
+        Option<&Velocitator>,
+        Option<&Observer>,
+        Option<&Separation>,
+        Option<&Alignment>,
+        Option<&Cohesion>,
+        Option<&KeepInBounds>,
+        Option<&KeepLevel>,
+    ), Without<Grounded>>,
+    query_others: Query<&Transform>,
+) {
+    let mut points: Vec<Vec<[f32; 3]>> = vec![Vec::new(); DebugLayer::ALL.len()];
+
+    if settings.enabled {
+        let shown = |layer| settings.is_shown(layer);
+        let steering_scale = settings.steering_scale;
+        let velocity_scale = settings.velocity_scale;
+
+        for (transform, flyer, velocitator, observer, separation, alignment, cohesion, keep_in_bounds, keep_level) in query_flyers.iter() {
+            let position = transform.translation;
+
+            if shown(DebugLayer::Separation) {
+                push_steering(&mut points[DebugLayer::Separation as usize], position, separation, steering_scale);
+            }
+            if shown(DebugLayer::Alignment) {
+                push_steering(&mut points[DebugLayer::Alignment as usize], position, alignment, steering_scale);
+            }
+            if shown(DebugLayer::Cohesion) {
+                push_steering(&mut points[DebugLayer::Cohesion as usize], position, cohesion, steering_scale);
+            }
+            if shown(DebugLayer::KeepInBounds) {
+                push_steering(&mut points[DebugLayer::KeepInBounds as usize], position, keep_in_bounds, steering_scale);
+            }
+            if shown(DebugLayer::KeepLevel) {
+                push_steering(&mut points[DebugLayer::KeepLevel as usize], position, keep_level, steering_scale);
+            }
+
+            if shown(DebugLayer::Neighbours) {
+                if let Some(observer) = observer {
+                    let neighbours = &mut points[DebugLayer::Neighbours as usize];
+                    for other in observer.observed.iter() {
+                        if let Ok(other_transform) = query_others.get(*other) {
+                            // Across the wrapped edge if that's nearer, like the observer sees it.
+                            let displacement = match &stuff_to_observe {
+                                Some(stuff_to_observe) => stuff_to_observe.displacement(position, other_transform.translation),
+                                None => other_transform.translation - position,
+                            };
+                            neighbours.push(position.to_array());
+                            neighbours.push((position + displacement).to_array());
+                        }
+                    }
+                }
+            }
+
+            if shown(DebugLayer::GoalVelocity) {
+                let goal = &mut points[DebugLayer::GoalVelocity as usize];
+                goal.push(position.to_array());
+                goal.push((position + flyer.goal_velocity * velocity_scale).to_array());
+            }
+            if shown(DebugLayer::Velocity) {
+                let velocity = velocitator.map_or(flyer.speed_linear * transform.forward(), |velocitator| velocitator.velocity);
+                let actual = &mut points[DebugLayer::Velocity as usize];
+                actual.push(position.to_array());
+                actual.push((position + velocity * velocity_scale).to_array());
+            }
+        }
+
+        if shown(DebugLayer::Cells) {
+            if let Some(stuff_to_observe) = &stuff_to_observe {
+                let cells = &mut points[DebugLayer::Cells as usize];
+                for (min, max) in stuff_to_observe.occupied_cells() {
+                    cells.extend(cell_outline(min, max).iter().map(|corner| corner.to_array()));
+                }
+            }
+        }
+    }
+
+    for (lines, mesh, mut visibility) in query_lines.iter_mut() {
+        let layer_points = std::mem::take(&mut points[lines.0 as usize]);
+        visibility.is_visible = !layer_points.is_empty();
+        if visibility.is_visible {
+            if let Some(mesh) = meshes.get_mut(mesh) {
+                *mesh = line_mesh(layer_points);
+            }
+        }
+    }
+}
@@ -3,7 +3,9 @@ use bevy::{
     prelude::*,
 };
 
+use crate::bounds::{Bounds, Containment};
 use crate::jay_math;
+use crate::simulation::{Interpolated, SimulationLabel, SimulationStage, SimulationTime};
 use crate::velocitator::Velocitator;
 
 /// The Flight plugin.
@@ -12,11 +14,20 @@ pub struct Flight;
 impl Plugin for Flight {
     fn build(&self, app: &mut App) {
         app
-            .add_system(flyer_goals_reduce_to_components_system)
-            .add_system(flyer_steering_system.after(flyer_goals_reduce_to_components_system))
-            .add_system(flyer_movement_system.after(flyer_steering_system))
-            .add_system(flyer_copy_velocity_system.after(flyer_movement_system))
-            .register_type::<Flyer>();
+            .add_system_set_to_stage(
+                SimulationStage,
+                SystemSet::new()
+                    .label(SimulationLabel::Flight)
+                    .after(SimulationLabel::Steer)
+                    .with_system(flyer_goals_reduce_to_components_system)
+                    .with_system(flyer_steering_system.after(flyer_goals_reduce_to_components_system))
+                    .with_system(flyer_movement_system.after(flyer_steering_system))
+                    .with_system(flyer_aerodynamics_system.after(flyer_steering_system))
+                    .with_system(flyer_boundary_system.after(flyer_movement_system).after(flyer_aerodynamics_system))
+                    .with_system(flyer_copy_velocity_system.after(flyer_boundary_system)),
+            )
+            .register_type::<Flyer>()
+            .register_type::<Aerodynamics>();
     }
 }
 
@@ -36,9 +47,14 @@ pub struct Flyer
     pub props: FlyerProps,
 }
 
+/// Marks a flyer which has landed. It stays put, and isn't part of anyone's
+/// flock (or steered at all) until it takes off again.
+#[derive(Component, Debug)]
+pub struct Grounded;
+
 /// Flying properties. You can change these during play to simulate different
 /// states such as applying new forces or gliding.
-#[derive(Reflect, Default)]
+#[derive(Reflect)]
 pub struct FlyerProps
 {
     pub accel_max: f32,
@@ -47,6 +63,26 @@ pub struct FlyerProps
     pub ang_spd_x_max: f32,
     pub ang_spd_y_max: f32,
     pub ang_z_from_y_spd: f32,
+    /// The fastest we'll try to climb (vertical speed).
+    pub climb_max: f32,
+    /// How fast we lose height regardless, e.g. when gliding. Only for
+    /// flyers without `Aerodynamics`, which lose height by themselves.
+    pub sink_rate: f32,
+}
+
+impl Default for FlyerProps {
+    fn default() -> Self {
+        FlyerProps {
+            accel_max: 0.0,
+            spd_min: 0.0,
+            spd_max: 0.0,
+            ang_spd_x_max: 0.0,
+            ang_spd_y_max: 0.0,
+            ang_z_from_y_spd: 0.0,
+            climb_max: f32::MAX,
+            sink_rate: 0.0,
+        }
+    }
 }
 
 /// The goal that a flyer would like to achieve, reduced to components.
@@ -58,20 +94,121 @@ pub struct FlyerGoalComponents
     pub ang_y: f32,
 }
 
+/// Opts a flyer into aerodynamic flight: rather than moving at its speed
+/// in whichever direction it faces, it's pushed around by gravity, lift,
+/// drag and the thrust of its flapping. It still steers towards its goal
+/// velocity, pitching up to climb and flapping to speed up, so it can be
+/// driven by the same goals. Units are per second (squared) and per unit
+/// of mass.
+#[derive(Reflect, Component, Default, Debug)]
+#[reflect(Component)]
+pub struct Aerodynamics
+{
+    pub velocity: Vec3,
+    pub gravity: f32,
+    /// Lift per airspeed squared, when flying straight into the wind.
+    pub lift: f32,
+    /// How much more lift (and sideways force, when turning) we get for
+    /// the nose pointing away from the direction of travel.
+    pub lift_slope: f32,
+    /// Beyond this angle (in radians) between the nose and the direction
+    /// of travel, we get no more lift.
+    pub stall_angle: f32,
+    /// Drag per airspeed squared.
+    pub drag: f32,
+    /// Acceleration from flapping flat out.
+    pub flap_thrust: f32,
+    /// How hard we're flapping, from 0 to 1.
+    pub throttle: f32,
+    /// The hardest we're allowed to flap, e.g. 0 while soaring.
+    pub max_throttle: f32,
+}
+
+/// How far below the goal speed we have to be to flap flat out.
+const THROTTLE_SPEED_RANGE: f32 = 20.0;
+/// How much wanting to climb makes us flap.
+const CLIMB_THROTTLE: f32 = 2.0;
+
+/// How hard to flap to get to the goal velocity: harder when we're too
+/// slow or want to climb.
+pub fn flap_throttle(goal_velocity: Vec3, velocity: Vec3) -> f32 {
+    let speed_deficit = goal_velocity.length() - velocity.length();
+    let climb = goal_velocity.normalize_or_zero().y - velocity.normalize_or_zero().y;
+    (speed_deficit / THROTTLE_SPEED_RANGE + climb.max(0.0) * CLIMB_THROTTLE).clamp(0.0, 1.0)
+}
+
+/// The acceleration on an aerodynamic flyer, given its velocity, which
+/// way it's facing and how hard it's flapping.
+pub fn aerodynamic_acceleration(aero: &Aerodynamics, velocity: Vec3, forward: Vec3, throttle: f32) -> Vec3 {
+    let gravity = -Vec3::Y * aero.gravity;
+    let thrust = forward * aero.flap_thrust * throttle;
+
+    let airspeed = velocity.length();
+    if airspeed <= 0.0 {
+        return gravity + thrust;
+    }
+    let travel = velocity / airspeed;
+    let pressure = airspeed * airspeed;
+
+    // Our wings lift us square to the direction of travel, towards our back...
+    let right = travel.cross(Vec3::Y);
+    let up = right.cross(travel).normalize_or_zero();
+    // ...and more so (or sideways) when the nose points away from it.
+    let off_nose = (forward - travel * forward.dot(travel)).clamp_length_max(aero.stall_angle.sin());
+    let lift = (up + off_nose * aero.lift_slope) * aero.lift * pressure;
+
+    let drag = -travel * aero.drag * pressure;
+
+    gravity + thrust + lift + drag
+}
+
+/// Moves aerodynamic flyers, facing them where they've steered to.
+fn flyer_aerodynamics_system(
+    time: Res<SimulationTime>,
+    mut query: Query<(&mut Flyer, &mut Aerodynamics, &mut Transform), Without<Grounded>>,
+) {
+    for (mut flyer, mut aero, mut transform) in query.iter_mut() {
+        transform.rotation = Quat::from_euler(EulerRot::YXZ, flyer.ang_y, flyer.ang_x, flyer.props.ang_z_from_y_spd * flyer.ang_y_vel);
+
+        aero.throttle = flap_throttle(flyer.goal_velocity, aero.velocity).min(aero.max_throttle);
+        let accel = aerodynamic_acceleration(&aero, aero.velocity, transform.forward(), aero.throttle);
+        aero.velocity += accel * time.delta_seconds();
+        transform.translation += aero.velocity * time.delta_seconds();
+
+        flyer.speed_linear = aero.velocity.length();
+        flyer.accel_linear = accel.dot(aero.velocity.normalize_or_zero());
+    }
+}
+
 fn flyer_copy_velocity_system(
-    mut query: Query<(&Flyer, &Transform, &mut Velocitator)>,
+    mut query: Query<(&Flyer, &Transform, Option<&Aerodynamics>, &mut Velocitator)>,
 ) {
-    for (flyer, transform, mut velocitator) in query.iter_mut() {
-        velocitator.velocity = flyer.speed_linear * transform.forward();
+    for (flyer, transform, aero, mut velocitator) in query.iter_mut() {
+        velocitator.velocity = match aero {
+            Some(aero) => aero.velocity,
+            None => flyer.speed_linear * transform.forward(),
+        };
+    }
+}
+
+/// Flattens a direction so that flying along it at the given speed
+/// doesn't climb faster than `climb_max`.
+pub fn limit_climb(direction: Vec3, speed: f32, climb_max: f32) -> Vec3 {
+    if speed <= 0.0 || direction.y * speed <= climb_max {
+        return direction;
     }
+    let y = (climb_max / speed).clamp(-1.0, 1.0);
+    let horizontal = Vec3::new(direction.x, 0.0, direction.z).normalize_or_zero();
+    horizontal * (1.0 - y * y).sqrt() + Vec3::Y * y
 }
 
 fn flyer_goals_reduce_to_components_system(
-    mut query: Query<(&Transform, &mut Flyer)>,
+    mut query: Query<(&Transform, &mut Flyer), Without<Grounded>>,
 ) {
     for (transform, mut flyer) in query.iter_mut() {
         let goal_speed = flyer.goal_velocity.length();
         let goal_direction = if goal_speed > 0.0 { flyer.goal_velocity / goal_speed } else { Vec3::ZERO };
+        let goal_direction = limit_climb(goal_direction, goal_speed, flyer.props.climb_max);
         let vel_dot = goal_direction.dot(transform.forward()).clamp(0.0, 1.0);
 
         flyer.goal_components.speed_linear = flyer.props.spd_min.lerp(goal_speed, vel_dot).min(flyer.props.spd_max);
@@ -82,8 +219,8 @@ fn flyer_goals_reduce_to_components_system(
 }
 
 fn flyer_steering_system(
-    time: Res<Time>,
-    mut query: Query<&mut Flyer>,
+    time: Res<SimulationTime>,
+    mut query: Query<&mut Flyer, Without<Grounded>>,
 ) {
     for mut flyer in query.iter_mut() {
         let (spd_new, accel_new) = jay_math::smooth_damp(
@@ -122,11 +259,108 @@ fn flyer_steering_system(
 }
 
 pub fn flyer_movement_system(
-    time: Res<Time>,
-    mut query: Query<(&Flyer, &mut Transform)>,
+    time: Res<SimulationTime>,
+    mut query: Query<(&Flyer, &mut Transform), (Without<Aerodynamics>, Without<Grounded>)>,
 ) {
     for (flyer, mut transform) in query.iter_mut() {
         transform.rotation = Quat::from_euler(EulerRot::YXZ, flyer.ang_y, flyer.ang_x, flyer.props.ang_z_from_y_spd * flyer.ang_y_vel);
         transform.translation = transform.translation + transform.forward() * flyer.speed_linear * time.delta_seconds();
+        transform.translation.y -= flyer.props.sink_rate * time.delta_seconds();
     }
-}
\ No newline at end of file
+}
+
+/// Holds flyers to the hard edges of the bounds, if there are any (see
+/// `BoundaryMode`). Wrapping teleports, so it snaps the interpolation too.
+fn flyer_boundary_system(
+    bounds: Option<Res<Bounds>>,
+    mut query: Query<(&mut Flyer, &mut Transform, Option<&mut Interpolated>, Option<&mut Aerodynamics>), Without<Grounded>>,
+) {
+    let bounds = match bounds {
+        Some(bounds) => bounds,
+        None => return,
+    };
+    for (mut flyer, mut transform, interpolated, aero) in query.iter_mut() {
+        // Aerodynamic flyers don't always go the way they face.
+        let heading = aero.as_ref().map_or(transform.forward(), |aero| aero.velocity);
+        match bounds.contain(transform.translation, heading) {
+            Containment::Inside => {}
+            Containment::Wrapped(position) => {
+                transform.translation = position;
+                if let Some(mut interpolated) = interpolated {
+                    interpolated.snap(*transform);
+                }
+            }
+            Containment::Reflected { position, direction } => {
+                transform.translation = position;
+                (flyer.ang_y, flyer.ang_x) = jay_math::vec3_to_yaw_pitch(direction.normalize_or_zero());
+                flyer.ang_x_vel = 0.0;
+                flyer.ang_y_vel = 0.0;
+                if let Some(mut aero) = aero {
+                    aero.velocity = direction;
+                }
+                transform.rotation = Quat::from_euler(EulerRot::YXZ, flyer.ang_y, flyer.ang_x, 0.0);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn crow() -> Aerodynamics {
+        Aerodynamics {
+            gravity: 30.0,
+            lift: 0.0045,
+            lift_slope: 5.0,
+            stall_angle: 0.3,
+            drag: 0.0025,
+            flap_thrust: 20.0,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn soaring_descends() {
+        let accel = aerodynamic_acceleration(&crow(), -Vec3::Z * 75.0, -Vec3::Z, 0.0);
+        assert!(accel.y < 0.0);
+        assert!(accel.z > 0.0, "drag should slow us down");
+    }
+
+    #[test]
+    fn flapping_nose_up_climbs() {
+        let nose_up = Vec3::new(0.0, 0.2, -1.0).normalize();
+        let accel = aerodynamic_acceleration(&crow(), -Vec3::Z * 75.0, nose_up, 1.0);
+        assert!(accel.y > 0.0);
+    }
+
+    #[test]
+    fn turning_pulls_sideways() {
+        let nose_right = Vec3::new(0.2, 0.0, -1.0).normalize();
+        let accel = aerodynamic_acceleration(&crow(), -Vec3::Z * 75.0, nose_right, 0.0);
+        assert!(accel.x > 0.0);
+    }
+
+    #[test]
+    fn still_flyers_just_fall() {
+        assert_eq!(aerodynamic_acceleration(&crow(), Vec3::ZERO, -Vec3::Z, 0.0), -Vec3::Y * 30.0);
+    }
+
+    #[test]
+    fn climb_is_limited() {
+        let steep = Vec3::new(0.0, 0.8, -0.6);
+        let limited = limit_climb(steep, 50.0, 10.0);
+        assert!((limited.y * 50.0 - 10.0).abs() < 1e-4);
+        assert!((limited.length() - 1.0).abs() < 1e-4);
+        assert!(limited.z < 0.0);
+        assert_eq!(limit_climb(steep, 50.0, f32::MAX), steep);
+        assert_eq!(limit_climb(-steep, 50.0, 0.0), -steep);
+    }
+
+    #[test]
+    fn throttle_for_speed_and_climb() {
+        assert_eq!(flap_throttle(-Vec3::Z * 60.0, -Vec3::Z * 75.0), 0.0);
+        assert_eq!(flap_throttle(-Vec3::Z * 100.0, -Vec3::Z * 75.0), 1.0);
+        assert!(flap_throttle(Vec3::new(0.0, 0.2, -1.0).normalize() * 75.0, -Vec3::Z * 75.0) > 0.0);
+    }
+}
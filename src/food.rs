@@ -0,0 +1,100 @@
+use bevy::{
+    prelude::*,
+};
+use rand::prelude::*;
+use crate::bounds::Bounds;
+use crate::scenario::FoodConfig;
+use crate::simulation::*;
+use crate::terrain::Terrain;
+
+/// The food plugin. Food sources which have been eaten slowly grow back.
+pub struct Food;
+
+impl Plugin for Food {
+    fn build(&self, app: &mut App) {
+        app
+            .add_system_to_stage(
+                SimulationStage,
+                food_regrow_system
+                    .label(SimulationLabel::Goals)
+                    .after(SimulationLabel::Boids),
+            );
+    }
+}
+
+/// Something on the ground to eat, like scraps or fallen fruit.
+#[derive(Component, Debug)]
+pub struct FoodSource {
+    pub amount: f32,
+    /// The most there can be.
+    pub capacity: f32,
+    pub regrow_per_second: f32,
+}
+
+impl FoodSource {
+    /// Takes up to `wanted` from the source, and returns how much we got.
+    pub fn eat(&mut self, wanted: f32) -> f32 {
+        let eaten = wanted.clamp(0.0, self.amount);
+        self.amount -= eaten;
+        eaten
+    }
+}
+
+fn food_regrow_system(time: Res<SimulationTime>, mut query: Query<&mut FoodSource>) {
+    for mut food in query.iter_mut() {
+        food.amount = (food.amount + food.regrow_per_second * time.delta_seconds()).min(food.capacity);
+    }
+}
+
+/// Spawns a food source, without any visuals.
+pub fn spawn_food_source(commands: &mut Commands, source: FoodSource, position: Vec3) -> Entity {
+    commands
+        .spawn_bundle(SpatialBundle {
+            transform: Transform::from_translation(position),
+            ..Default::default()
+        })
+        .insert(source)
+        .insert(Name::new("Food"))
+        .id()
+}
+
+/// Scatters full food sources over the ground in the config's region (or
+/// anywhere in the bounds well clear of the margin), and returns them so
+/// that visuals can be added.
+pub fn scatter_food(
+    commands: &mut Commands,
+    rng: &mut impl Rng,
+    bounds: &Bounds,
+    terrain: Option<&Terrain>,
+    config: &FoodConfig,
+) -> Vec<Entity> {
+    let (min, max) = config.spawn_box(bounds);
+
+    (0..config.count)
+        .map(|_| {
+            let x = rng.gen_range(min[0]..=max[0]);
+            let z = rng.gen_range(min[2]..=max[2]);
+            let y = terrain.map_or(bounds.y_min, |terrain| terrain.height_at(x, z));
+            let source = FoodSource {
+                amount: config.capacity,
+                capacity: config.capacity,
+                regrow_per_second: config.regrow_per_second,
+            };
+            spawn_food_source(commands, source, Vec3::new(x, y, z))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn eating_takes_what_there_is() {
+        let mut food = FoodSource { amount: 10.0, capacity: 50.0, regrow_per_second: 1.0 };
+        assert_eq!(food.eat(4.0), 4.0);
+        assert_eq!(food.eat(10.0), 6.0);
+        assert_eq!(food.eat(1.0), 0.0);
+        assert_eq!(food.amount, 0.0);
+    }
+}
@@ -0,0 +1,216 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    time::Duration,
+};
+
+use bevy::{
+    prelude::*,
+    app::{AppExit, ScheduleRunnerSettings},
+};
+use big_brain::prelude::*;
+use crate::boids::Species;
+use crate::bounds::*;
+use crate::clusters::*;
+use crate::crows::*;
+use crate::flight::*;
+use crate::food::*;
+use crate::metrics::*;
+use crate::observe::*;
+use crate::navigation::Navigation;
+use crate::obstacles::*;
+use crate::predators::*;
+use crate::recorder::*;
+use crate::scenario::{Scenario, ScenarioError};
+use crate::terrain::{insert_scenario_terrain, Terrain};
+use crate::simulation::*;
+use crate::FlockingPlugins;
+
+/// Settings for a headless run.
+pub struct HeadlessSettings {
+    pub ticks: u32,
+    pub tick_rate: f64,
+    pub seed: Option<u64>,
+    /// Where to write the metrics at the end, as CSV.
+    pub metrics: Option<String>,
+}
+
+/// Builds an app which runs the flock without a window, renderer or any
+/// assets. Call `run()` on it, or `update()` it by hand (e.g. in tests).
+/// Every update is exactly one simulation tick, and we don't wait between
+/// updates, so runs go as fast as they can. Fails if the scenario's
+/// heightmap won't load.
+pub fn build_app(scenario: Scenario, settings: HeadlessSettings) -> Result<App, ScenarioError> {
+    let bounds = scenario.bounds.to_bounds();
+    // Keep every tick's metrics if we're going to write them out at the end.
+    let keep_all_metrics = settings.metrics.is_some();
+
+    let mut app = App::new();
+    insert_scenario_terrain(&mut app, &scenario)?;
+    app
+        .insert_resource(ScheduleRunnerSettings::run_loop(Duration::ZERO))
+        .insert_resource(SimulationSettings {
+            tick_rate: settings.tick_rate,
+            lockstep: true,
+            seed: settings.seed,
+            max_ticks: Some(settings.ticks as u64),
+            ..Default::default()
+        })
+        .add_plugins(MinimalPlugins)
+        .add_plugin(BigBrainPlugin)
+        .add_plugins(FlockingPlugins)
+        .add_plugin(Crows)
+        .add_plugin(Predators)
+        .add_plugin(Obstacles)
+        .add_plugin(Navigation)
+        .add_plugin(Food)
+        .add_plugin(Recorder)
+        .add_plugin(Metrics)
+        .add_plugin(Clusters)
+        .insert_resource(StuffsToObserve::from_bounds(&bounds))
+        .insert_resource(bounds)
+        .insert_resource(scenario.weights.clone())
+        .insert_resource(SpeciesWeights::from_species(&scenario.species))
+        .insert_resource(scenario)
+        .insert_resource(settings)
+        .add_startup_system(headless_startup)
+        .add_system_to_stage(CoreStage::Last, headless_tick_system);
+    app.world.resource_mut::<FlockMetrics>().keep_all = keep_all_metrics;
+    Ok(app)
+}
+
+fn headless_startup(
+    mut commands: Commands,
+    mut rng: ResMut<SimulationRng>,
+    bounds: Res<Bounds>,
+    scenario: Res<Scenario>,
+    terrain: Option<Res<Terrain>>,
+) {
+    for (id, species) in scenario.species.iter().enumerate() {
+        spawn_species(&mut commands, &mut rng.0, &bounds, Species(id), species, None);
+    }
+    for predator in scenario.predators.iter() {
+        spawn_predators(&mut commands, &mut rng.0, &bounds, predator, None);
+    }
+    for obstacle in scenario.obstacles.iter() {
+        spawn_obstacle(&mut commands, obstacle.to_shape(), Vec3::from(obstacle.position));
+    }
+    if let Some(food) = &scenario.food {
+        scatter_food(&mut commands, &mut rng.0, &bounds, terrain.as_deref(), food);
+    }
+}
+
+/// Exits once we've run for long enough, printing a small summary of where
+/// the flock ended up.
+fn headless_tick_system(
+    settings: Res<HeadlessSettings>,
+    sim_time: Res<SimulationTime>,
+    seed: Res<SimulationSeed>,
+    query: Query<(&Flyer, &Transform)>,
+    mut recording: ResMut<Recording>,
+    metrics: Res<FlockMetrics>,
+    clusters: Res<FlockClusters>,
+    mut app_exit_events: EventWriter<AppExit>,
+) {
+    if sim_time.tick() < settings.ticks as u64 {
+        return;
+    }
+
+    let mut count = 0;
+    let mut centroid = Vec3::ZERO;
+    let mut speed = 0.0;
+    for (flyer, transform) in query.iter() {
+        count += 1;
+        centroid += transform.translation;
+        speed += flyer.speed_linear;
+    }
+    if count > 0 {
+        centroid /= count as f32;
+        speed /= count as f32;
+    }
+
+    println!(
+        "Headless run (seed {}) finished after {} ticks: {} crows, centroid {}, mean speed {:.2}",
+        seed.0, sim_time.tick(), count, centroid, speed
+    );
+    if let Some(latest) = metrics.latest() {
+        println!(
+            "Polarization {:.3}, milling {:.3}, nearest neighbour {:.2}, {:.2} neighbours",
+            latest.polarization, latest.milling, latest.nearest_neighbour, latest.neighbours
+        );
+    }
+    println!(
+        "{} flocks (largest {}, mean size {:.1}), {} stragglers",
+        clusters.count(), clusters.largest(), clusters.mean_size(), clusters.stragglers
+    );
+
+    if let Some(path) = recording.path().map(|path| path.display().to_string()) {
+        let frames = recording.frames();
+        match recording.stop() {
+            Ok(()) => println!("Recorded {} ticks to {}", frames, path),
+            Err(err) => eprintln!("{}: {}", path, err),
+        }
+    }
+
+    if let Some(path) = &settings.metrics {
+        let written = File::create(path).and_then(|file| {
+            let mut writer = BufWriter::new(file);
+            metrics.write_csv(&mut writer)?;
+            writer.flush()
+        });
+        match written {
+            Ok(()) => println!("Wrote metrics for {} ticks to {}", metrics.history().len(), path),
+            Err(err) => eprintln!("{}: {}", path, err),
+        }
+    }
+
+    app_exit_events.send(AppExit);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_scenario() -> Scenario {
+        let mut scenario = Scenario::default();
+        scenario.species[0].count = 20;
+        scenario
+    }
+
+    #[test]
+    fn headless_flock_runs_and_exits() {
+        for ticks in [0, 10] {
+            let mut app = build_app(test_scenario(), HeadlessSettings { ticks, tick_rate: 60.0, seed: None, metrics: None }).unwrap();
+            // One more update than needed, which shouldn't tick any more.
+            for _ in 0..=ticks {
+                app.update();
+            }
+
+            let mut query = app.world.query::<(&Flyer, &Transform)>();
+            assert_eq!(query.iter(&app.world).count(), 20);
+            for (_, transform) in query.iter(&app.world) {
+                assert!(transform.translation.is_finite());
+            }
+
+            assert_eq!(app.world.resource::<SimulationTime>().tick(), ticks as u64);
+            assert_eq!(app.world.resource::<FlockMetrics>().history().len(), ticks as usize);
+            assert!(!app.world.resource::<Events<AppExit>>().is_empty());
+        }
+    }
+
+    fn positions_after(seed: u64, ticks: u32) -> Vec<Vec3> {
+        let mut app = build_app(test_scenario(), HeadlessSettings { ticks, tick_rate: 60.0, seed: Some(seed), metrics: None }).unwrap();
+        for _ in 0..ticks {
+            app.update();
+        }
+
+        let mut query = app.world.query_filtered::<&Transform, With<Flyer>>();
+        query.iter(&app.world).map(|transform| transform.translation).collect()
+    }
+
+    #[test]
+    fn same_seed_same_flock() {
+        assert_eq!(positions_after(7, 30), positions_after(7, 30));
+        assert_ne!(positions_after(7, 30), positions_after(8, 30));
+    }
+}
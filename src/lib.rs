@@ -0,0 +1,65 @@
+// Bevy code commonly triggers these lints and they may be important signals
+// about code quality. They are sometimes hard to avoid though, and the CI
+// workflow treats them as errors, so this allows them throughout the project.
+// Feel free to delete this line.
+#![allow(clippy::too_many_arguments, clippy::type_complexity)]
+
+//! A crude boids implementation in Bevy, plus the house crows which use it.
+//! Created for Dinacon 2022 in Sri Lanka.
+//!
+//! Add `FlockingPlugins` to your app (along with a `Bounds` and a
+//! `StuffsToObserve` resource) to get observation, the boids rules,
+//! steering and the flight model. New steering behaviours can be added
+//! from anywhere with `SteeringAppExt::add_steering_behaviour`. The
+//! `crows` module shows one way to put it all together, and is what the
+//! demo binary uses.
+
+use bevy::{
+    app::PluginGroupBuilder,
+    prelude::*,
+};
+
+pub mod anim;
+pub mod boids;
+pub mod bounds;
+pub mod cli;
+pub mod clusters;
+pub mod crows;
+pub mod debug_draw;
+pub mod flight;
+pub mod food;
+pub mod headless;
+pub mod jay_math;
+pub mod metrics;
+pub mod navigation;
+pub mod observe;
+pub mod obstacles;
+pub mod predators;
+pub mod recorder;
+pub mod replay;
+pub mod scenario;
+pub mod simulation;
+pub mod steering;
+pub mod terrain;
+pub mod velocitator;
+
+pub use boids::Boids;
+pub use flight::Flight;
+pub use observe::Observe;
+pub use simulation::Simulation;
+pub use steering::Steering;
+
+/// The plugins which make up the flocking simulation itself:
+/// `Simulation`, `Observe`, `Boids`, `Steering` and `Flight`.
+pub struct FlockingPlugins;
+
+impl PluginGroup for FlockingPlugins {
+    fn build(&mut self, group: &mut PluginGroupBuilder) {
+        group
+            .add(Simulation)
+            .add(Observe)
+            .add(Boids)
+            .add(Steering)
+            .add(Flight);
+    }
+}
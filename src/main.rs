@@ -6,34 +6,49 @@
 
 /// A crude boids demo in Bevy.
 /// Created for Dinacon 2022 in Sri Lanka.
+/// The simulation itself lives in the library; this is just the crow scene.
 
-mod anim;
-mod boids;
-mod observe;
-mod bounds;
-mod flight;
-mod jay_math;
-mod velocitator;
-mod crows;
-
-use std::f32::consts::{PI, TAU};
-
-use anim::*;
-use boids::*;
-use observe::*;
-use bounds::*;
-use flight::*;
-use crows::*;
+use std::{
+    f32::consts::PI,
+    fs::File,
+    io::{BufWriter, Write},
+};
+
+use bevy_boids_demo::{
+    anim::*,
+    boids::{SeparationFalloff, Species},
+    observe::*,
+    bounds::*,
+    clusters::*,
+    flight::*,
+    food::*,
+    metrics::*,
+    crows::*,
+    debug_draw::*,
+    predators::*,
+    recorder::*,
+    replay::*,
+    navigation::Navigation,
+    obstacles::*,
+    terrain::{insert_scenario_terrain, Terrain},
+    cli::CliArgs,
+    headless::{self, HeadlessSettings},
+    scenario::Scenario,
+    simulation::*,
+    FlockingPlugins,
+};
 use bevy::{
     prelude::*,
     render::camera::Viewport,
+    utils::HashMap,
     window::{WindowId, WindowResized},
     core_pipeline::clear_color::ClearColorConfig,
     render::camera::ScalingMode,
+    render::view::RenderLayers,
+    transform::TransformSystem,
 };
 use big_brain::prelude::*;
 use bevy_egui::{egui, EguiContext, EguiPlugin};
-use rand::prelude::*;
 
 pub const LAUNCHER_TITLE: &str = "Bevy Boids Demo";
 
@@ -53,60 +68,206 @@ struct FollowCamera
 }
 
 fn main() {
+    let args = CliArgs::from_env();
+
+    let scenario = match &args.scenario {
+        Some(path) => match Scenario::load(path) {
+            Ok(scenario) => scenario,
+            Err(err) => {
+                eprintln!("{}: {}", path, err);
+                std::process::exit(1);
+            }
+        },
+        None => Scenario::default(),
+    };
 
     // The overall bounds of our simulation.
-    let dem_bounds = Bounds::new(
-        50.0,
-        0.0,
-        600.0,
-        0.0,
-        250.0,
-        0.0,
-        600.0,
-        50.0,
-    );
-    
-    App::new()
-        .insert_resource(WindowDescriptor {
-            title: LAUNCHER_TITLE.to_string(),
-            canvas: Some("#bevy".to_string()),
-            fit_canvas_to_parent: true,
+    let dem_bounds = scenario.bounds.to_bounds();
+
+    if let Some(path) = &args.replay {
+        run_replay(path, scenario, dem_bounds);
+        return;
+    }
+
+    if args.headless {
+        let mut app = match headless::build_app(scenario, HeadlessSettings {
+            ticks: args.ticks,
+            tick_rate: args.tick_rate,
+            seed: args.seed,
+            metrics: args.metrics.clone(),
+        }) {
+            Ok(app) => app,
+            Err(err) => {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+        };
+        if let Some(recording) = start_recording(&args) {
+            app.insert_resource(recording);
+        }
+        app.run();
+        return;
+    }
+
+    let mut app = App::new();
+    if let Err(err) = insert_scenario_terrain(&mut app, &scenario) {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    }
+    app
+        .insert_resource(window_descriptor())
+        .insert_resource(SimulationSettings {
+            tick_rate: args.tick_rate,
+            seed: args.seed,
             ..Default::default()
         })
         .add_plugins(DefaultPlugins)
         .add_startup_system(load_icon)
         .add_plugin(BigBrainPlugin)
         .add_plugin(JayAnimation)
-        .add_plugin(Observe)
-        .add_plugin(Boids)
-        .add_plugin(Flight)
+        .add_plugins(FlockingPlugins)
+        .add_plugin(Crows)
+        .add_plugin(Predators)
+        .add_plugin(Obstacles)
+        .add_plugin(Navigation)
+        .add_plugin(Food)
+        .add_plugin(Recorder)
+        .add_plugin(Metrics)
+        .add_plugin(Clusters)
+        .add_plugin(DebugDraw)
         .add_plugin(EguiPlugin)
         .insert_resource(AmbientLight {
             color: Color::WHITE,
             brightness: 1.0,
         })
-        .insert_resource(StuffsToObserve::new(dem_bounds.cells_x, dem_bounds.cells_z, dem_bounds.cell_size))
+        .insert_resource(StuffsToObserve::from_bounds(&dem_bounds))
         .insert_resource(ClearColor(Color::rgb(1.0, 0.8, 0.5)))
         .insert_resource(dem_bounds)
-        .insert_resource(CrowGlobalProps {
-            separation_weight: 2.0,
-            alignment_weight: 2.0,
-            cohesion_weight: 2.0,
-            keep_in_bounds_weight: 0.5,
-            keep_level_weight: 0.5,
-        })
+        .insert_resource(scenario.weights.clone())
+        .insert_resource(SpeciesWeights::from_species(&scenario.species))
+        .insert_resource(scenario)
+        .add_startup_system(setup_scene)
         .add_startup_system(startup)
-        .add_system(crows::flyer_goal_velocity_from_boids_system)
-        .add_system(keep_in_bounds_system)
-        .add_system(keep_level_system)
-        .add_system(stamina_update_system)
+        .init_resource::<FlockTint>()
         .add_system(set_camera_viewports_system)
         .add_system(crow_ui_system)
-        .add_system(stamina_update_system)
-        .add_system(follow_cam_system.after(flyer_movement_system))
-        .add_system_to_stage(BigBrainStage::Actions, flap_action_system)
-        .add_system_to_stage(BigBrainStage::Scorers, flap_scorer_system)
-        .run();
+        .add_system(metrics_ui_system)
+        .add_system(flock_tint_system)
+        .add_system_to_stage(
+            CoreStage::PostUpdate,
+            follow_cam_system
+                .after(SimulationLabel::Interpolate)
+                .before(TransformSystem::TransformPropagate),
+        );
+    if let Some(recording) = start_recording(&args) {
+        app.insert_resource(recording);
+    }
+    app.run();
+}
+
+/// Recording from the start, if asked to.
+fn start_recording(args: &CliArgs) -> Option<Recording> {
+    args.record.as_ref().map(|path| {
+        let mut recording = Recording::default();
+        if let Err(err) = recording.start(path, RecordFormat::from_path(path), args.tick_rate) {
+            eprintln!("{}: {}", path, err);
+            std::process::exit(1);
+        }
+        recording
+    })
+}
+
+fn window_descriptor() -> WindowDescriptor {
+    WindowDescriptor {
+        title: LAUNCHER_TITLE.to_string(),
+        canvas: Some("#bevy".to_string()),
+        fit_canvas_to_parent: true,
+        ..Default::default()
+    }
+}
+
+/// Plays back a recording in the scenario's scene, without simulating
+/// anything.
+fn run_replay(path: &str, scenario: Scenario, bounds: Bounds) {
+    let run = match read_recording(path) {
+        Ok(run) => run,
+        Err(err) => {
+            eprintln!("{}: {}", path, err);
+            std::process::exit(1);
+        }
+    };
+    let frames = ReplayFrames::new(run.frames, run.scales);
+
+    let mut app = App::new();
+    if let Err(err) = insert_scenario_terrain(&mut app, &scenario) {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    }
+    app
+        .insert_resource(window_descriptor())
+        .add_plugins(DefaultPlugins)
+        .add_plugin(JayAnimation)
+        .add_plugin(Replay)
+        .add_plugin(EguiPlugin)
+        .insert_resource(AmbientLight {
+            color: Color::WHITE,
+            brightness: 1.0,
+        })
+        .insert_resource(ClearColor(Color::rgb(1.0, 0.8, 0.5)))
+        .insert_resource(Playback {
+            tick: frames.first_tick() as f64,
+            tick_rate: run.tick_rate,
+            ..Default::default()
+        })
+        .insert_resource(frames)
+        .insert_resource(bounds)
+        .insert_resource(scenario)
+        .add_startup_system(setup_scene)
+        .add_startup_system(replay_startup)
+        .add_system(set_camera_viewports_system)
+        .add_system(replay_ui_system)
+        .add_system_to_stage(
+            CoreStage::PostUpdate,
+            follow_cam_system.before(TransformSystem::TransformPropagate),
+        );
+    app.run();
+}
+
+/// Spawns the replayed flyers, all looking like the scenario's first species.
+fn replay_startup(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    frames: Res<ReplayFrames>,
+    scenario: Res<Scenario>,
+) {
+    let model = scenario.species.first()
+        .and_then(|species| species.model.as_ref())
+        .map(|model| asset_server.load(model.as_str()));
+    spawn_tracks(&mut commands, &frames, model);
+}
+
+fn replay_ui_system(
+    mut egui_context: ResMut<EguiContext>,
+    frames: Res<ReplayFrames>,
+    mut playback: ResMut<Playback>,
+) {
+    let (first, last) = (frames.first_tick() as f64, frames.last_tick() as f64);
+    egui::Window::new("Replay").show(egui_context.ctx_mut(), |ui| {
+        ui.label(format!("Tick {:.0} of {}", playback.tick, frames.last_tick()));
+        if playback.playing {
+            if ui.button("Pause").clicked() {
+                playback.playing = false;
+            }
+        } else if ui.button("Play").clicked() {
+            // Start again if we'd got to the end.
+            if playback.tick >= last {
+                playback.tick = first;
+            }
+            playback.playing = true;
+        }
+        ui.add(egui::Slider::new(&mut playback.tick, first..=last).text("tick"));
+        ui.add(egui::Slider::new(&mut playback.speed, 0.1..=10.0).logarithmic(true).text("speed"));
+    });
 }
 
 
@@ -118,34 +279,263 @@ fn load_icon(mut commands: Commands, asset_server: Res<AssetServer>) {
     });
 }
 
+/// What's been typed into the recording part of the UI.
+struct RecorderUi {
+    path: String,
+    format: RecordFormat,
+    error: Option<String>,
+}
+
+impl Default for RecorderUi {
+    fn default() -> Self {
+        RecorderUi {
+            path: String::from("recording.csv"),
+            format: RecordFormat::Csv,
+            error: None,
+        }
+    }
+}
+
 fn crow_ui_system(
     mut egui_context: ResMut<EguiContext>,
     mut common_props: ResMut<CrowGlobalProps>,
+    mut species_weights: ResMut<SpeciesWeights>,
+    mut recording: ResMut<Recording>,
+    mut recorder_ui: Local<RecorderUi>,
+    mut debug_draw: ResMut<DebugDrawSettings>,
+    seed: Res<SimulationSeed>,
+    settings: Res<SimulationSettings>,
 ) {
+    // Slide copies, so that the resources only change (and the systems
+    // watching them only do anything) when a slider actually moves.
+    let mut props = common_props.clone();
+    let mut weights = species_weights.species.clone();
     egui::Window::new("House Crows").show(egui_context.ctx_mut(), |ui| {
+        ui.label(format!("Seed: {} (run with --seed {0} to repeat)", seed.0));
         ui.label("Boids Weights:");
-        ui.add(egui::Slider::new(&mut common_props.separation_weight, 0.0..=5.0).text("separation"));
-        ui.add(egui::Slider::new(&mut common_props.alignment_weight, 0.0..=5.0).text("alignment"));
-        ui.add(egui::Slider::new(&mut common_props.cohesion_weight, 0.0..=5.0).text("cohesion"));
+        ui.add(egui::Slider::new(&mut props.separation_weight, 0.0..=5.0).text("separation"));
+        ui.add(egui::Slider::new(&mut props.alignment_weight, 0.0..=5.0).text("alignment"));
+        ui.add(egui::Slider::new(&mut props.cohesion_weight, 0.0..=5.0).text("cohesion"));
+        ui.label("Separation:");
+        ui.add(egui::Slider::new(&mut props.separation_radius, 1.0..=50.0).text("radius"));
+        egui::ComboBox::from_label("falloff")
+            .selected_text(format!("{:?}", props.separation_falloff))
+            .show_ui(ui, |ui| {
+                for falloff in SeparationFalloff::ALL {
+                    ui.selectable_value(&mut props.separation_falloff, falloff, format!("{:?}", falloff));
+                }
+            });
         ui.label("Other Weights:");
-        ui.add(egui::Slider::new(&mut common_props.keep_in_bounds_weight, 0.0..=2.0).text("keep in bounds"));
-        ui.add(egui::Slider::new(&mut common_props.keep_level_weight, 0.0..=2.0).text("keep level"));
+        ui.add(egui::Slider::new(&mut props.keep_in_bounds_weight, 0.0..=2.0).text("keep in bounds"));
+        ui.add(egui::Slider::new(&mut props.keep_level_weight, 0.0..=2.0).text("keep level"));
+        ui.add(egui::Slider::new(&mut props.flee_weight, 0.0..=5.0).text("flee"));
+        ui.add(egui::Slider::new(&mut props.avoid_obstacles_weight, 0.0..=5.0).text("avoid obstacles"));
+        ui.add(egui::Slider::new(&mut props.seek_weight, 0.0..=5.0).text("seek"));
+        ui.label("Species Weights:");
+        for (id, set) in weights.iter_mut().enumerate() {
+            egui::CollapsingHeader::new(&set.name).id_source(id).show(ui, |ui| {
+                ui.add(egui::Slider::new(&mut set.separation, 0.0..=5.0).text("separation"));
+                ui.add(egui::Slider::new(&mut set.alignment, 0.0..=5.0).text("alignment"));
+                ui.add(egui::Slider::new(&mut set.cohesion, 0.0..=5.0).text("cohesion"));
+                ui.add(egui::Slider::new(&mut set.keep_level, 0.0..=5.0).text("keep level"));
+            });
+        }
+        ui.label("Recording:");
+        if recording.is_recording() {
+            if let Some(path) = recording.path() {
+                ui.label(format!("{} ticks to {}", recording.frames(), path.display()));
+            }
+            if ui.button("Stop").clicked() {
+                recorder_ui.error = recording.stop().err().map(|err| err.to_string());
+            }
+        } else {
+            ui.text_edit_singleline(&mut recorder_ui.path);
+            egui::ComboBox::from_label("format")
+                .selected_text(format!("{:?}", recorder_ui.format))
+                .show_ui(ui, |ui| {
+                    for format in RecordFormat::ALL {
+                        ui.selectable_value(&mut recorder_ui.format, format, format!("{:?}", format));
+                    }
+                });
+            if ui.button("Start").clicked() {
+                let started = recording.start(&recorder_ui.path, recorder_ui.format, settings.tick_rate);
+                recorder_ui.error = started.err().map(|err| err.to_string());
+            }
+        }
+        if let Some(error) = &recorder_ui.error {
+            ui.label(format!("Couldn't record: {}", error));
+        }
+        ui.checkbox(&mut debug_draw.enabled, "Debug Draw (F3)");
+        if debug_draw.enabled {
+            for layer in DebugLayer::ALL {
+                ui.checkbox(debug_draw.shown_mut(layer), layer.name());
+            }
+            ui.add(egui::Slider::new(&mut debug_draw.steering_scale, 0.0..=5.0).text("steering scale"));
+            ui.add(egui::Slider::new(&mut debug_draw.velocity_scale, 0.0..=1.0).text("velocity scale"));
+        }
+    });
+    if props != *common_props {
+        *common_props = props;
+    }
+    if weights != species_weights.species {
+        species_weights.species = weights;
+    }
+}
+
+
+/// What's been typed into the metrics window, and how the last export went.
+struct MetricsUi {
+    path: String,
+    message: Option<String>,
+}
+
+impl Default for MetricsUi {
+    fn default() -> Self {
+        MetricsUi {
+            path: String::from("metrics.csv"),
+            message: None,
+        }
+    }
+}
+
+fn metrics_ui_system(
+    mut egui_context: ResMut<EguiContext>,
+    mut metrics: ResMut<FlockMetrics>,
+    mut metrics_ui: Local<MetricsUi>,
+    clusters: Res<FlockClusters>,
+    mut cluster_settings: ResMut<ClusterSettings>,
+    mut flock_tint: ResMut<FlockTint>,
+) {
+    egui::Window::new("Flock Metrics").show(egui_context.ctx_mut(), |ui| {
+        if let Some(latest) = metrics.latest() {
+            ui.label(format!("Flyers: {}", latest.count));
+            ui.label(format!("Polarization: {:.3}", latest.polarization));
+            ui.label(format!("Milling: {:.3}", latest.milling));
+            ui.label(format!("Nearest neighbour: {:.2}", latest.nearest_neighbour));
+            ui.label(format!("Neighbours: {:.2}", latest.neighbours));
+            ui.label(format!("Speed: {:.2}", latest.speed));
+        }
+        ui.label(format!("{} ticks measured", metrics.history().len()));
+        let keep_all = format!("keep every tick (not just the last {})", metrics.capacity);
+        ui.checkbox(&mut metrics.keep_all, keep_all);
+        ui.text_edit_singleline(&mut metrics_ui.path);
+        ui.horizontal(|ui| {
+            if ui.button("Export").clicked() {
+                let written = File::create(&metrics_ui.path).and_then(|file| {
+                    let mut writer = BufWriter::new(file);
+                    metrics.write_csv(&mut writer)?;
+                    writer.flush()
+                });
+                let message = match written {
+                    Ok(()) => format!("Exported to {}", metrics_ui.path),
+                    Err(err) => format!("Couldn't export: {}", err),
+                };
+                metrics_ui.message = Some(message);
+            }
+            if ui.button("Clear").clicked() {
+                metrics.clear();
+            }
+        });
+        if let Some(message) = &metrics_ui.message {
+            ui.label(message);
+        }
+        ui.label("Flocks:");
+        ui.label(format!(
+            "{} flocks (largest {}, mean size {:.1}), {} stragglers",
+            clusters.count(), clusters.largest(), clusters.mean_size(), clusters.stragglers
+        ));
+        ui.add(egui::Slider::new(&mut cluster_settings.linkage_distance, 1.0..=100.0).text("linkage distance"));
+        ui.add(egui::Slider::new(&mut cluster_settings.min_flock_size, 1..=20).text("min flock size"));
+        ui.checkbox(&mut flock_tint.enabled, "colour by flock");
     });
 }
 
+/// Tints each crow in its flock's colour, by swapping the materials of its
+/// model for copies in that colour.
+#[derive(Default)]
+struct FlockTint {
+    enabled: bool,
+    /// The copies made so far, by the original material and colour.
+    tinted: HashMap<(Handle<StandardMaterial>, usize), Handle<StandardMaterial>>,
+}
+
+/// How many colours the flocks go round.
+const FLOCK_COLOURS: usize = 12;
+
+fn flock_colour(colour: usize) -> Color {
+    // Hues spread out by the golden angle, so flocks with nearby ids
+    // don't get similar colours.
+    Color::hsl((colour as f32 * 137.5) % 360.0, 0.9, 0.6)
+}
+
+/// The material part of a crow's model had before it was tinted.
+#[derive(Component)]
+struct UntintedMaterial(Handle<StandardMaterial>);
+
+/// Tints every part of each crow's model once tinting is turned on, and
+/// puts the original materials back for crows that aren't in a flock (or
+/// once it's turned off).
+fn flock_tint_system(
+    mut commands: Commands,
+    mut flock_tint: ResMut<FlockTint>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    query: Query<(Entity, Option<&FlockId>), With<Observer>>,
+    query_children: Query<&Children>,
+    mut query_parts: Query<(&mut Handle<StandardMaterial>, Option<&UntintedMaterial>)>,
+) {
+    let mut stack = Vec::new();
+    for (entity, flock_id) in query.iter() {
+        let colour = flock_id
+            .filter(|_| flock_tint.enabled)
+            .map(|flock_id| flock_id.0 as usize % FLOCK_COLOURS);
+
+        stack.push(entity);
+        while let Some(part) = stack.pop() {
+            if let Ok(children) = query_children.get(part) {
+                stack.extend(children.iter());
+            }
+            let (mut material, untinted) = match query_parts.get_mut(part) {
+                Ok(found) => found,
+                Err(_) => continue,
+            };
+
+            let original = match untinted {
+                Some(UntintedMaterial(original)) => original.clone(),
+                None => {
+                    commands.entity(part).insert(UntintedMaterial(material.clone()));
+                    material.clone()
+                }
+            };
+            let wanted = match colour {
+                Some(colour) => flock_tint.tinted
+                    .entry((original.clone(), colour))
+                    .or_insert_with(|| {
+                        let mut tinted = materials.get(&original).cloned().unwrap_or_default();
+                        tinted.base_color = flock_colour(colour);
+                        materials.add(tinted)
+                    })
+                    .clone(),
+                None => original,
+            };
+            if *material != wanted {
+                *material = wanted;
+            }
+        }
+    }
+}
 
 fn follow_cam_system(
     mut query_cameras: Query<(&Camera, &mut Transform, &mut FollowCamera)>,
-    query_target: Query<(&Flyer, &Transform, Entity), Without<FollowCamera>>,
+    query_target: Query<(&Transform, Entity), (Or<(With<Flyer>, With<ReplayTrack>)>, Without<FollowCamera>)>,
 )
 {
     for (_, mut camera_transform, mut follow_camera) in query_cameras.iter_mut() {
-        if let Ok((_, flyer_transform, _)) = query_target.get(follow_camera.target_entity) {
+        if let Ok((flyer_transform, _)) = query_target.get(follow_camera.target_entity) {
             camera_transform.translation = flyer_transform.translation + flyer_transform.forward() * 4.0 - flyer_transform.right() * 4.0 + flyer_transform.up() * 2.0;
             camera_transform.look_at(flyer_transform.translation, -Vec3::Y);
             return;
         }
-        for (_, _, entity) in query_target.iter()
+        for (_, entity) in query_target.iter()
         {
             follow_camera.target_entity = entity;
             return;
@@ -153,12 +543,15 @@ fn follow_cam_system(
     }
 }
 
-fn startup(
+/// The cameras, ground, light and obstacles, which are the same whether
+/// we're simulating or replaying.
+fn setup_scene(
     mut commands: Commands,
-    asset_server: Res<AssetServer>,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
     bounds: Res<Bounds>,
+    scenario: Res<Scenario>,
+    terrain: Option<Res<Terrain>>,
 ) {
     let mid_point = Vec3::new(bounds.x_min + 0.5 * bounds.x_size, bounds.y_min + 0.5 * bounds.y_size, bounds.z_min + 0.5 * bounds.z_size);
     let mid_top = Vec3::new(mid_point.x, bounds.y_max, mid_point.z);
@@ -180,7 +573,9 @@ fn startup(
 
         ..default()
     })
-        .insert(TopDownCam);
+        .insert(TopDownCam)
+        // The debug cells only make sense from above.
+        .insert(RenderLayers::from_layers(&[0, TOP_DOWN_LAYER]));
 
 
     let side_cam_pos = Vec3::new(mid_point.x, mid_point.y, bounds.z_max);
@@ -250,16 +645,20 @@ fn startup(
             target_entity: Entity::from_raw(1000)
         });
 
-    // Box ground.
-    commands.spawn_bundle(PbrBundle {
-        mesh: meshes.add(Mesh::from(shape::Box {
+    // Ground: the terrain if we have one, otherwise a box.
+    let ground_mesh = match &terrain {
+        Some(terrain) => terrain.to_mesh(),
+        None => Mesh::from(shape::Box {
             min_x: bounds.x_min,
             max_x: bounds.x_max,
             min_z: bounds.z_min,
             max_z: bounds.z_max,
             min_y: -10.0,
             max_y: 0.0,
-        })),
+        }),
+    };
+    commands.spawn_bundle(PbrBundle {
+        mesh: meshes.add(ground_mesh),
         material: materials.add(Color::rgb(0.9, 0.7, 0.4).into()),
         transform: Transform::identity(),
         ..default()
@@ -281,28 +680,64 @@ fn startup(
         ..default()
     });
 
+    let obstacle_material = materials.add(Color::rgb(0.35, 0.3, 0.25).into());
+    for obstacle in scenario.obstacles.iter() {
+        let shape = obstacle.to_shape();
+        let (mesh, offset) = obstacle_mesh(&shape);
+        let mesh = meshes.add(mesh);
+        let material = obstacle_material.clone();
+        let entity = spawn_obstacle(&mut commands, shape, Vec3::from(obstacle.position));
+        commands
+            .entity(entity)
+            .with_children(|parent| {
+                parent.spawn_bundle(PbrBundle {
+                    mesh,
+                    material,
+                    transform: Transform::from_translation(offset),
+                    ..default()
+                });
+            });
+    }
+}
 
-    let count = 180;
-    let mut rng = rand::thread_rng();
-
-    for _ in 0..count
-    {
-        let m = bounds.margin + 50.0;
-        let x = rng.gen_range(bounds.x_min + m..bounds.x_max - m);
-        let y = rng.gen_range(bounds.y_min + m..bounds.y_max - m);
-        let z = rng.gen_range(bounds.z_min + m..bounds.z_max - m);
-
-        let rot = rng.gen_range(-TAU..TAU) * 0.5;
-
-        let pos = Vec3::from((x, y, z));
+/// Spawns the flock, predators and food.
+fn startup(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut rng: ResMut<SimulationRng>,
+    bounds: Res<Bounds>,
+    scenario: Res<Scenario>,
+    terrain: Option<Res<Terrain>>,
+) {
+    for (id, species) in scenario.species.iter().enumerate() {
+        let model = species.model.as_ref().map(|model| asset_server.load(model.as_str()));
+        spawn_species(&mut commands, &mut rng.0, &bounds, Species(id), species, model);
+    }
+    for predator in scenario.predators.iter() {
+        let model = predator.model.as_ref().map(|model| asset_server.load(model.as_str()));
+        spawn_predators(&mut commands, &mut rng.0, &bounds, predator, model);
+    }
 
-        make_instance(
-            &mut commands,
-            &asset_server,
-            "house_crow.glb",
-            pos,
-            Quat::from_axis_angle(Vec3::Y, rot),
-        );
+    if let Some(food) = &scenario.food {
+        let food_mesh = meshes.add(Mesh::from(shape::UVSphere {
+            radius: 6.0,
+            ..default()
+        }));
+        let food_material = materials.add(Color::rgb(0.6, 0.1, 0.15).into());
+        for entity in scatter_food(&mut commands, &mut rng.0, &bounds, terrain.as_deref(), food) {
+            commands
+                .entity(entity)
+                .with_children(|parent| {
+                    parent.spawn_bundle(PbrBundle {
+                        mesh: food_mesh.clone(),
+                        material: food_material.clone(),
+                        transform: Transform::from_scale(Vec3::new(1.0, 0.4, 1.0)),
+                        ..default()
+                    });
+                });
+        }
     }
 }
 
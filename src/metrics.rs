@@ -0,0 +1,257 @@
+use std::{
+    collections::VecDeque,
+    io::{self, Write},
+};
+
+use bevy::{
+    prelude::*,
+    utils::HashMap,
+};
+use crate::bounds::Bounds;
+use crate::flight::Grounded;
+use crate::observe::Observer;
+use crate::simulation::*;
+use crate::velocitator::Velocitator;
+
+/// The metrics plugin. Every tick, works out some standard order
+/// parameters for the flock (everything with an `Observer` and a
+/// `Velocitator` which isn't on the ground) and keeps the last few
+/// thousand ticks' in `FlockMetrics`.
+pub struct Metrics;
+
+impl Plugin for Metrics {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<FlockMetrics>()
+            .add_system_to_stage(
+                SimulationStage,
+                metrics_system
+                    .label(SimulationLabel::Metrics)
+                    .after(SimulationLabel::Flight),
+            );
+    }
+}
+
+/// How orderly the flock is at one tick.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct OrderParameters {
+    /// How many flyers were measured.
+    pub count: usize,
+    /// The length of the mean heading: 1 when everyone flies the same way,
+    /// and near 0 when they're all over the place.
+    pub polarization: f32,
+    /// The length of the mean angular momentum about the centroid (with
+    /// positions and velocities normalised): 1 for a perfect mill, where
+    /// everyone circles the middle the same way.
+    pub milling: f32,
+    /// The mean distance to each flyer's nearest neighbour, out of those it
+    /// can see (flyers which can't see anyone are left out).
+    pub nearest_neighbour: f32,
+    /// The mean number of neighbours each flyer can see.
+    pub neighbours: f32,
+    pub speed: f32,
+}
+
+pub const METRICS_CSV_HEADER: &str = "tick,count,polarization,milling,nearest_neighbour,neighbours,speed";
+
+/// How many ticks `FlockMetrics` keeps by default: a minute at 60 ticks
+/// per second.
+pub const METRICS_HISTORY_TICKS: usize = 3600;
+
+/// The latest order parameters, along with the last `capacity` ticks' (or
+/// every tick's so far, with `keep_all`).
+pub struct FlockMetrics {
+    history: VecDeque<(u64, OrderParameters)>,
+    pub capacity: usize,
+    /// Keep every tick, e.g. to export them all at the end of a run.
+    pub keep_all: bool,
+}
+
+impl Default for FlockMetrics {
+    fn default() -> Self {
+        FlockMetrics {
+            history: VecDeque::new(),
+            capacity: METRICS_HISTORY_TICKS,
+            keep_all: false,
+        }
+    }
+}
+
+impl FlockMetrics {
+    pub fn latest(&self) -> Option<OrderParameters> {
+        self.history.back().map(|(_, parameters)| *parameters)
+    }
+
+    /// The tick and order parameters for every tick kept, oldest first.
+    pub fn history(&self) -> &VecDeque<(u64, OrderParameters)> {
+        &self.history
+    }
+
+    /// Adds a tick's parameters, dropping the oldest if we're full.
+    pub fn push(&mut self, tick: u64, parameters: OrderParameters) {
+        if !self.keep_all {
+            while self.history.len() >= self.capacity.max(1) {
+                self.history.pop_front();
+            }
+        }
+        self.history.push_back((tick, parameters));
+    }
+
+    pub fn clear(&mut self) {
+        self.history.clear();
+    }
+
+    /// Writes the whole history out as CSV, under `METRICS_CSV_HEADER`.
+    pub fn write_csv(&self, writer: &mut impl Write) -> io::Result<()> {
+        writeln!(writer, "{}", METRICS_CSV_HEADER)?;
+        for (tick, parameters) in self.history.iter() {
+            writeln!(
+                writer,
+                "{},{},{},{},{},{},{}",
+                tick,
+                parameters.count,
+                parameters.polarization,
+                parameters.milling,
+                parameters.nearest_neighbour,
+                parameters.neighbours,
+                parameters.speed,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Works out the order parameters for some flyers, given how far each is
+/// from its nearest visible neighbour (if it can see any). The centroid for
+/// milling doesn't know about wrapping.
+pub fn order_parameters(
+    positions: &[Vec3],
+    velocities: &[Vec3],
+    neighbour_counts: &[usize],
+    nearest_distances: &[Option<f32>],
+) -> OrderParameters {
+    let count = positions.len();
+    if count == 0 {
+        return OrderParameters::default();
+    }
+    let n = count as f32;
+
+    let centroid = positions.iter().fold(Vec3::ZERO, |sum, position| sum + *position) / n;
+    let mut heading_sum = Vec3::ZERO;
+    let mut momentum_sum = Vec3::ZERO;
+    let mut speed_sum = 0.0;
+    for (position, velocity) in positions.iter().zip(velocities.iter()) {
+        let heading = velocity.normalize_or_zero();
+        heading_sum += heading;
+        momentum_sum += (*position - centroid).normalize_or_zero().cross(heading);
+        speed_sum += velocity.length();
+    }
+
+    let (nearest_sum, nearest_count) = nearest_distances.iter()
+        .flatten()
+        .fold((0.0, 0), |(sum, count), distance| (sum + distance, count + 1));
+    let nearest_neighbour = if nearest_count > 0 { nearest_sum / nearest_count as f32 } else { 0.0 };
+
+    OrderParameters {
+        count,
+        polarization: heading_sum.length() / n,
+        milling: momentum_sum.length() / n,
+        nearest_neighbour,
+        neighbours: neighbour_counts.iter().sum::<usize>() as f32 / n,
+        speed: speed_sum / n,
+    }
+}
+
+fn metrics_system(
+    time: Res<SimulationTime>,
+    bounds: Option<Res<Bounds>>,
+    mut metrics: ResMut<FlockMetrics>,
+    query: Query<(Entity, &Transform, &Velocitator, &Observer), Without<Grounded>>,
+) {
+    let position_of: HashMap<Entity, Vec3> = query.iter()
+        .map(|(entity, transform, ..)| (entity, transform.translation))
+        .collect();
+
+    let mut positions = Vec::new();
+    let mut velocities = Vec::new();
+    let mut neighbour_counts = Vec::new();
+    let mut nearest_distances = Vec::new();
+    for (_, transform, velocitator, observer) in query.iter() {
+        positions.push(transform.translation);
+        velocities.push(velocitator.velocity);
+        neighbour_counts.push(observer.observed.len());
+
+        // What we can see is nearest first, so the first flyer is the nearest.
+        let nearest = observer.observed.iter().find_map(|other| position_of.get(other));
+        nearest_distances.push(nearest.map(|nearest| match &bounds {
+            Some(bounds) => bounds.displacement(transform.translation, *nearest).length(),
+            None => transform.translation.distance(*nearest),
+        }));
+    }
+
+    let parameters = order_parameters(&positions, &velocities, &neighbour_counts, &nearest_distances);
+    metrics.push(time.tick(), parameters);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn approx_eq(one: f32, two: f32) -> bool {
+        (one - two).abs() < 0.0001
+    }
+
+    #[test]
+    fn aligned_flock_is_polarized() {
+        let positions = [Vec3::ZERO, Vec3::X * 10.0, Vec3::X * 30.0];
+        let velocities = [Vec3::Z * 5.0; 3];
+        let parameters = order_parameters(&positions, &velocities, &[1, 2, 1], &[Some(10.0), Some(10.0), Some(20.0)]);
+        assert_eq!(parameters.count, 3);
+        assert!(approx_eq(parameters.polarization, 1.0));
+        assert!(approx_eq(parameters.nearest_neighbour, 40.0 / 3.0));
+        assert!(approx_eq(parameters.neighbours, 4.0 / 3.0));
+        assert!(approx_eq(parameters.speed, 5.0));
+    }
+
+    #[test]
+    fn circling_flock_mills() {
+        let positions = [Vec3::X, Vec3::Z, -Vec3::X, -Vec3::Z];
+        let velocities = [-Vec3::Z, Vec3::X, Vec3::Z, -Vec3::X];
+        let parameters = order_parameters(&positions, &velocities, &[0; 4], &[None; 4]);
+        assert!(approx_eq(parameters.polarization, 0.0));
+        assert!(approx_eq(parameters.milling, 1.0));
+    }
+
+    #[test]
+    fn nobody_to_measure() {
+        assert_eq!(order_parameters(&[], &[], &[], &[]), OrderParameters::default());
+        assert_eq!(order_parameters(&[Vec3::ONE], &[Vec3::X], &[0], &[None]).nearest_neighbour, 0.0);
+        let lonely = order_parameters(&[Vec3::ZERO, Vec3::X, Vec3::Z], &[Vec3::X; 3], &[1, 1, 0], &[Some(1.0), Some(3.0), None]);
+        assert!(approx_eq(lonely.nearest_neighbour, 2.0));
+    }
+
+    #[test]
+    fn csv_history() {
+        let mut metrics = FlockMetrics::default();
+        metrics.push(3, OrderParameters { count: 2, polarization: 0.5, milling: 0.25, nearest_neighbour: 10.0, neighbours: 1.0, speed: 40.0 });
+        let mut csv = Vec::new();
+        metrics.write_csv(&mut csv).unwrap();
+        assert_eq!(String::from_utf8(csv).unwrap(), format!("{}\n3,2,0.5,0.25,10,1,40\n", METRICS_CSV_HEADER));
+    }
+
+    #[test]
+    fn history_is_bounded_unless_keeping_all() {
+        let mut metrics = FlockMetrics { capacity: 3, ..Default::default() };
+        for tick in 0..5 {
+            metrics.push(tick, OrderParameters::default());
+        }
+        let ticks: Vec<u64> = metrics.history().iter().map(|(tick, _)| *tick).collect();
+        assert_eq!(ticks, vec![2, 3, 4]);
+
+        metrics.keep_all = true;
+        for tick in 5..10 {
+            metrics.push(tick, OrderParameters::default());
+        }
+        assert_eq!(metrics.history().len(), 8);
+    }
+}
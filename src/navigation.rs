@@ -0,0 +1,298 @@
+use bevy::{
+    prelude::*,
+};
+use crate::bounds::Bounds;
+use crate::flight::*;
+use crate::simulation::*;
+use crate::steering::{SteeringAppExt, SteeringBehaviour};
+
+/// The navigation plugin, for sending flyers somewhere: `Seek` flies
+/// straight at a target, `Arrive` slows down as it gets there and
+/// `Patrol` visits a list of waypoints in turn.
+pub struct Navigation;
+
+impl Plugin for Navigation {
+    fn build(&self, app: &mut App) {
+        app
+            .add_system_set_to_stage(
+                SimulationStage,
+                SystemSet::new()
+                    .label(SimulationLabel::Goals)
+                    .after(SimulationLabel::Boids)
+                    .with_system(seek_system)
+                    .with_system(arrive_system)
+                    .with_system(patrol_system),
+            )
+            .add_steering_behaviour::<Seek>()
+            .add_steering_behaviour::<Arrive>()
+            .add_steering_behaviour::<Patrol>();
+    }
+}
+
+/// Where to go: somewhere fixed, or wherever something is (e.g. a lure).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NavTarget {
+    Point(Vec3),
+    Entity(Entity),
+}
+
+/// A goal to fly straight at a target, flat out. The weight is 1 while
+/// the target exists and 0 otherwise.
+#[derive(Component, Debug)]
+pub struct Seek {
+    pub target: NavTarget,
+    pub target_vel: Vec3,
+    pub weight: f32,
+}
+
+/// A goal to fly to a target and slow down on the way in. Flyers can't go
+/// slower than their minimum speed, so they end up circling it.
+#[derive(Component, Debug)]
+pub struct Arrive {
+    pub target: NavTarget,
+    /// How far out we start slowing down.
+    pub slowing_radius: f32,
+    pub target_vel: Vec3,
+    pub weight: f32,
+}
+
+/// A goal to fly through some waypoints in order.
+#[derive(Component, Debug)]
+pub struct Patrol {
+    pub waypoints: Vec<Vec3>,
+    /// Which waypoint we're heading for.
+    pub current: usize,
+    /// How close counts as having reached a waypoint.
+    pub reach_radius: f32,
+    /// Go back to the first waypoint after the last, or arrive at the last
+    /// and stay there.
+    pub looping: bool,
+    pub target_vel: Vec3,
+    pub weight: f32,
+}
+
+impl SteeringBehaviour for Seek {
+    fn steer(&self) -> Vec3 {
+        self.target_vel
+    }
+
+    fn weight(&self) -> f32 {
+        self.weight
+    }
+}
+
+impl SteeringBehaviour for Arrive {
+    fn steer(&self) -> Vec3 {
+        self.target_vel
+    }
+
+    fn weight(&self) -> f32 {
+        self.weight
+    }
+}
+
+impl SteeringBehaviour for Patrol {
+    fn steer(&self) -> Vec3 {
+        self.target_vel
+    }
+
+    fn weight(&self) -> f32 {
+        self.weight
+    }
+}
+
+impl Seek {
+    pub fn new(target: NavTarget) -> Seek {
+        Seek {
+            target,
+            target_vel: Vec3::ZERO,
+            weight: 0.0,
+        }
+    }
+}
+
+impl Arrive {
+    pub fn new(target: NavTarget, slowing_radius: f32) -> Arrive {
+        Arrive {
+            target,
+            slowing_radius,
+            target_vel: Vec3::ZERO,
+            weight: 0.0,
+        }
+    }
+}
+
+impl Patrol {
+    pub fn new(waypoints: Vec<Vec3>, reach_radius: f32, looping: bool) -> Patrol {
+        Patrol {
+            waypoints,
+            current: 0,
+            reach_radius,
+            looping,
+            target_vel: Vec3::ZERO,
+            weight: 0.0,
+        }
+    }
+
+    /// Moves on to the next waypoint if we're close enough to the current
+    /// one, and returns the one to head for (if any).
+    pub fn advance(&mut self, position: Vec3) -> Option<Vec3> {
+        let waypoint = *self.waypoints.get(self.current)?;
+        let last = self.current + 1 == self.waypoints.len();
+        if position.distance(waypoint) <= self.reach_radius && (self.looping || !last) {
+            self.current = (self.current + 1) % self.waypoints.len();
+        }
+        self.waypoints.get(self.current).copied()
+    }
+
+    /// Whether we're on our way to the last waypoint, and won't go on.
+    pub fn on_final_leg(&self) -> bool {
+        !self.looping && self.current + 1 >= self.waypoints.len()
+    }
+}
+
+/// The velocity to fly straight along the given displacement, flat out.
+pub fn seek_velocity(displacement: Vec3, speed_max: f32) -> Vec3 {
+    displacement.normalize_or_zero() * speed_max
+}
+
+/// Like `seek_velocity`, but slowing down in proportion to the distance
+/// once we're within the slowing radius.
+pub fn arrive_velocity(displacement: Vec3, speed_max: f32, slowing_radius: f32) -> Vec3 {
+    let distance = displacement.length();
+    if distance <= 0.0 {
+        return Vec3::ZERO;
+    }
+    let speed = if distance < slowing_radius { speed_max * distance / slowing_radius } else { speed_max };
+    displacement / distance * speed
+}
+
+/// Where a target is now, if it still exists.
+fn target_position(target: NavTarget, query_targets: &Query<&Transform>) -> Option<Vec3> {
+    match target {
+        NavTarget::Point(point) => Some(point),
+        NavTarget::Entity(entity) => query_targets.get(entity).ok().map(|transform| transform.translation),
+    }
+}
+
+/// The way to go, which might be across a wrapped edge of the bounds.
+fn displacement_to(bounds: &Option<Res<Bounds>>, from: Vec3, to: Vec3) -> Vec3 {
+    match bounds {
+        Some(bounds) => bounds.displacement(from, to),
+        None => to - from,
+    }
+}
+
+/// The system which updates the target velocity and weight on Seek.
+fn seek_system(
+    mut query: Query<(&Transform, &Flyer, &mut Seek)>,
+    query_targets: Query<&Transform>,
+    bounds: Option<Res<Bounds>>,
+) {
+    for (transform, flyer, mut seek) in query.iter_mut() {
+        match target_position(seek.target, &query_targets) {
+            Some(target) => {
+                let vel = flyer.speed_linear * transform.forward();
+                let displacement = displacement_to(&bounds, transform.translation, target);
+                seek.target_vel = seek_velocity(displacement, flyer.props.spd_max) - vel;
+                seek.weight = 1.0;
+            }
+            None => {
+                seek.target_vel = Vec3::ZERO;
+                seek.weight = 0.0;
+            }
+        }
+    }
+}
+
+/// The system which updates the target velocity and weight on Arrive.
+fn arrive_system(
+    mut query: Query<(&Transform, &Flyer, &mut Arrive)>,
+    query_targets: Query<&Transform>,
+    bounds: Option<Res<Bounds>>,
+) {
+    for (transform, flyer, mut arrive) in query.iter_mut() {
+        match target_position(arrive.target, &query_targets) {
+            Some(target) => {
+                let vel = flyer.speed_linear * transform.forward();
+                let displacement = displacement_to(&bounds, transform.translation, target);
+                arrive.target_vel = arrive_velocity(displacement, flyer.props.spd_max, arrive.slowing_radius) - vel;
+                arrive.weight = 1.0;
+            }
+            None => {
+                arrive.target_vel = Vec3::ZERO;
+                arrive.weight = 0.0;
+            }
+        }
+    }
+}
+
+/// The system which moves patrols along and updates their target velocity
+/// and weight. The last waypoint of a patrol which doesn't loop is
+/// arrived at rather than flown through.
+fn patrol_system(
+    mut query: Query<(&Transform, &Flyer, &mut Patrol)>,
+    bounds: Option<Res<Bounds>>,
+) {
+    for (transform, flyer, mut patrol) in query.iter_mut() {
+        match patrol.advance(transform.translation) {
+            Some(waypoint) => {
+                let vel = flyer.speed_linear * transform.forward();
+                let displacement = displacement_to(&bounds, transform.translation, waypoint);
+                let goal = if patrol.on_final_leg() {
+                    arrive_velocity(displacement, flyer.props.spd_max, patrol.reach_radius * 2.0)
+                } else {
+                    seek_velocity(displacement, flyer.props.spd_max)
+                };
+                patrol.target_vel = goal - vel;
+                patrol.weight = 1.0;
+            }
+            None => {
+                patrol.target_vel = Vec3::ZERO;
+                patrol.weight = 0.0;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seek_is_flat_out() {
+        assert_eq!(seek_velocity(Vec3::new(0.0, 0.0, -5.0), 10.0), Vec3::new(0.0, 0.0, -10.0));
+        assert_eq!(seek_velocity(Vec3::ZERO, 10.0), Vec3::ZERO);
+    }
+
+    #[test]
+    fn arrive_slows_down_inside_radius() {
+        assert_eq!(arrive_velocity(Vec3::new(100.0, 0.0, 0.0), 10.0, 50.0), Vec3::new(10.0, 0.0, 0.0));
+        assert_eq!(arrive_velocity(Vec3::new(25.0, 0.0, 0.0), 10.0, 50.0), Vec3::new(5.0, 0.0, 0.0));
+        assert_eq!(arrive_velocity(Vec3::ZERO, 10.0, 50.0), Vec3::ZERO);
+    }
+
+    #[test]
+    fn patrol_loops() {
+        let mut patrol = Patrol::new(vec![Vec3::ZERO, Vec3::X * 100.0], 10.0, true);
+        assert_eq!(patrol.advance(Vec3::X * 50.0), Some(Vec3::ZERO));
+        assert_eq!(patrol.advance(Vec3::X * 5.0), Some(Vec3::X * 100.0));
+        assert_eq!(patrol.advance(Vec3::X * 95.0), Some(Vec3::ZERO));
+        assert!(!patrol.on_final_leg());
+    }
+
+    #[test]
+    fn patrol_stops_at_the_end() {
+        let mut patrol = Patrol::new(vec![Vec3::ZERO, Vec3::X * 100.0], 10.0, false);
+        patrol.advance(Vec3::ZERO);
+        assert!(patrol.on_final_leg());
+        assert_eq!(patrol.advance(Vec3::X * 100.0), Some(Vec3::X * 100.0));
+        assert_eq!(patrol.current, 1);
+    }
+
+    #[test]
+    fn empty_patrol_goes_nowhere() {
+        let mut patrol = Patrol::new(Vec::new(), 10.0, true);
+        assert_eq!(patrol.advance(Vec3::ZERO), None);
+    }
+}
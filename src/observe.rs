@@ -1,6 +1,11 @@
+use std::f32::consts::PI;
+
 use bevy::{
     prelude::*,
 };
+use crate::bounds::{wrap_offset, Bounds};
+use crate::flight::Grounded;
+use crate::simulation::{SimulationLabel, SimulationStage};
 
 // Our own plugin:
 pub struct Observe;
@@ -8,79 +13,214 @@ pub struct Observe;
 impl Plugin for Observe {
     fn build(&self, app: &mut App) {
         app
-            .add_system(observation_system_update_cells)
-            .add_system(observation_system_update_observed.after(observation_system_update_cells))
-            .add_system(observation_system_update_hashmap.after(observation_system_update_observed));
+            .add_system_set_to_stage(
+                SimulationStage,
+                SystemSet::new()
+                    .label(SimulationLabel::Observe)
+                    .with_system(observation_system_update_cells)
+                    .with_system(observation_system_update_observed.after(observation_system_update_cells))
+                    .with_system(observation_system_clear_grounded)
+                    .with_system(observation_system_update_hashmap.after(observation_system_update_observed)),
+            );
     }
 }
 
-#[derive(Component, Debug, Default)]
+/// Something which observes the things around it. Only things in the
+/// neighbouring cells are considered, so a perception radius larger than
+/// the cell size won't see any further.
+#[derive(Component, Debug)]
 pub struct Observer {
     pub cell: usize,
+    /// What we can currently see, nearest first (never includes ourselves).
     pub observed: Vec<Entity>,
+    /// How far away we can see things.
+    pub perception_radius: f32,
+    /// Half the angle of our vision cone, in radians. Anything further
+    /// than this from our forward direction is in our blind spot.
+    /// PI (the default) sees all the way around.
+    pub fov_half_angle: f32,
+    /// If set, only the nearest this many things are observed
+    /// (topological rather than metric neighbours).
+    pub max_neighbours: Option<usize>,
+}
+
+impl Default for Observer {
+    fn default() -> Self {
+        Observer {
+            cell: 0,
+            observed: Vec::new(),
+            perception_radius: f32::MAX,
+            fov_half_angle: PI,
+            max_neighbours: None,
+        }
+    }
+}
+
+impl Observer {
+    /// Whether we'd see something at the given displacement from us,
+    /// given the direction we're facing.
+    pub fn perceives(&self, forward: Vec3, displacement: Vec3) -> bool {
+        let dist_sqr = displacement.length_squared();
+        if dist_sqr > self.perception_radius * self.perception_radius {
+            return false;
+        }
+        if self.fov_half_angle >= PI || dist_sqr <= 0.0 {
+            return true;
+        }
+        forward.angle_between(displacement) <= self.fov_half_angle
+    }
 }
 
 // A resource which collects observable thingies by spatial hashing.
+// The cells form a 3D grid covering the bounds.
 pub struct StuffsToObserve {
     stuff: Vec<Vec<Entity>>,
+    origin: Vec3,
     cell_size: f32,
     width: usize,
+    height: usize,
     depth: usize,
+    /// The sizes along x and z if the world wraps around (see `BoundaryMode::Wrap`).
+    wrap_size: Option<Vec2>,
 }
 
 impl StuffsToObserve {
-    pub fn new(width: usize, depth: usize, cell_size: f32) -> StuffsToObserve {
+    pub fn new(origin: Vec3, width: usize, height: usize, depth: usize, cell_size: f32) -> StuffsToObserve {
+        // Always have at least one cell along each axis.
+        let width = width.max(1);
+        let height = height.max(1);
+        let depth = depth.max(1);
+
         let mut stuff = Vec::new();
-        let size = width * depth;
+        let size = width * height * depth;
         for _ in 0..size {
             stuff.push(Vec::new());
         }
         StuffsToObserve {
             stuff,
+            origin,
             cell_size,
             width,
+            height,
             depth,
+            wrap_size: None,
+        }
+    }
+
+    /// Makes the grid wrap around on x and z, so things near opposite
+    /// edges are neighbours.
+    pub fn wrapping(mut self, x_size: f32, z_size: f32) -> StuffsToObserve {
+        self.wrap_size = Some(Vec2::new(x_size, z_size));
+        self
+    }
+
+    /// Makes a grid which covers the given bounds.
+    pub fn from_bounds(bounds: &Bounds) -> StuffsToObserve {
+        let grid = StuffsToObserve::new(
+            Vec3::new(bounds.x_min, bounds.y_min, bounds.z_min),
+            bounds.cells_x,
+            bounds.cells_y,
+            bounds.cells_z,
+            bounds.cell_size,
+        );
+        if bounds.wraps() {
+            grid.wrapping(bounds.x_size, bounds.z_size)
+        } else {
+            grid
+        }
+    }
+
+    /// The shortest way from one point to another, which might be across
+    /// a wrapped edge.
+    pub fn displacement(&self, from: Vec3, to: Vec3) -> Vec3 {
+        let displacement = to - from;
+        match self.wrap_size {
+            Some(size) => Vec3::new(
+                wrap_offset(displacement.x, size.x),
+                displacement.y,
+                wrap_offset(displacement.z, size.y),
+            ),
+            None => displacement,
         }
     }
+
+    /// The lowest and highest corners of a cell.
+    pub fn cell_bounds(&self, cell: usize) -> (Vec3, Vec3) {
+        let x = cell % self.width;
+        let y = (cell / self.width) % self.height;
+        let z = cell / (self.width * self.height);
+        let min = self.origin + Vec3::new(x as f32, y as f32, z as f32) * self.cell_size;
+        (min, min + Vec3::splat(self.cell_size))
+    }
+
+    /// The bounds of every cell with something in it.
+    pub fn occupied_cells(&self) -> impl Iterator<Item=(Vec3, Vec3)> + '_ {
+        self.stuff.iter()
+            .enumerate()
+            .filter(|(_, stuff)| !stuff.is_empty())
+            .map(|(cell, _)| self.cell_bounds(cell))
+    }
 }
 
 impl StuffsToObserve {
+    /// The given cell plus its (up to) 26 neighbours. If we wrap, the
+    /// neighbours carry on around the far side.
     fn collect_cells(&self, cell: usize) -> Vec<usize>
     {
-        let mut all_cells = Vec::new();
+        let mut all_cells = Vec::with_capacity(27);
 
         let me = cell as isize;
         let w = self.width as isize;
+        let h = self.height as isize;
         let d = self.depth as isize;
 
         let x_me = me % w;
-        let z_me = me / w;
+        let y_me = (me / w) % h;
+        let z_me = me / (w * h);
+
+        let wraps = self.wrap_size.is_some();
 
         for x in x_me - 1..x_me + 2 // note ranges are [min..max)
         {
-            for z in z_me - 1..z_me + 2
+            for y in y_me - 1..y_me + 2
             {
-                if x >= 0 && x < w && z >= 0 && z < d
+                for z in z_me - 1..z_me + 2
                 {
-                    all_cells.push((x + z * w) as usize);
+                    let (x, z) = if wraps { (x.rem_euclid(w), z.rem_euclid(d)) } else { (x, z) };
+                    if x >= 0 && x < w && y >= 0 && y < h && z >= 0 && z < d
+                    {
+                        let near_cell = (x + y * w + z * w * h) as usize;
+                        // Small grids can wrap onto the same cell twice.
+                        if !all_cells.contains(&near_cell) {
+                            all_cells.push(near_cell);
+                        }
+                    }
                 }
             }
         }
 
         all_cells
     }
+
+    fn hash(&self, pos: Vec3) -> usize
+    {
+        hash_function(pos - self.origin, self.cell_size, self.width, self.height, self.depth)
+    }
 }
 
-// Our crude spatial-hash function.
-fn hash_function(pos: Vec3, cell_size: f32, width: usize, depth: usize) -> usize
+// Our crude spatial-hash function. Positions are relative to the grid's origin.
+fn hash_function(pos: Vec3, cell_size: f32, width: usize, height: usize, depth: usize) -> usize
 {
     if cell_size <= 0.
     { return 0; }
 
-    let x = (f32::floor(pos.x / cell_size) as usize).clamp(0, width - 1);
-    let z = (f32::floor(pos.z / cell_size) as usize).clamp(0, depth - 1);
+    let cell_of = |value: f32, count: usize| (f32::floor(value / cell_size) as isize).clamp(0, count as isize - 1) as usize;
 
-    x + z * width
+    let x = cell_of(pos.x, width);
+    let y = cell_of(pos.y, height);
+    let z = cell_of(pos.z, depth);
+
+    x + y * width + z * width * height
 }
 
 fn observation_system_update_cells(
@@ -88,38 +228,63 @@ fn observation_system_update_cells(
     mut observables: Query<(&mut Observer, &Transform)>)
 {
     for (mut obs, transform) in observables.iter_mut() {
-        obs.cell = hash_function(transform.translation, stuff_to_observe.cell_size, stuff_to_observe.width, stuff_to_observe.depth);
+        obs.cell = stuff_to_observe.hash(transform.translation);
     }
 }
 
 fn observation_system_update_observed(
     stuff_to_observe: Res<StuffsToObserve>,
-    mut observers: Query<&mut Observer>)
+    mut observers: Query<(&mut Observer, &Transform, Entity), Without<Grounded>>,
+    query_others: Query<&Transform>)
 {
     // let mut observed_count = 0;
     // let mut observer_count = 0;
-    // let mut observed_cells = 0;
-    for mut obs in observers.iter_mut() {
-        obs.observed.clear();
+    let mut candidates: Vec<(f32, Entity)> = Vec::new();
+    for (mut obs, transform, me) in observers.iter_mut() {
+        candidates.clear();
+        let forward = transform.forward();
         let near_cells = stuff_to_observe.collect_cells(obs.cell);
         for near_cell in near_cells.iter()
         {
             for entity in stuff_to_observe.stuff[*near_cell].iter() {
-                obs.observed.push(*entity);
+                if *entity == me { continue; }
+
+                if let Ok(other_transform) = query_others.get(*entity) {
+                    let displacement = stuff_to_observe.displacement(transform.translation, other_transform.translation);
+                    if obs.perceives(forward, displacement) {
+                        candidates.push((displacement.length_squared(), *entity));
+                    }
+                }
             }
-            // observed_cells += 1;
         }
+
+        // Nearest first.
+        candidates.sort_by(|a, b| a.0.total_cmp(&b.0));
+        if let Some(max_neighbours) = obs.max_neighbours {
+            candidates.truncate(max_neighbours);
+        }
+
+        obs.observed.clear();
+        obs.observed.extend(candidates.iter().map(|(_, entity)| *entity));
         // observed_count += obs.observed.len();
         // observer_count += 1;
     }
     // let observed_avg = observed_count as f32 / observer_count as f32;
-    // let observed_cells_avg = observed_cells as f32 / observer_count as f32;
-    // println!("observers: {:?}; observed_avg: {:?}; observed_cells_avg: {:?}", observer_count, observed_avg, observed_cells_avg);
+    // println!("observers: {:?}; observed_avg: {:?}", observer_count, observed_avg);
 }
 
+/// Grounded things don't look around, so forget whoever they could see
+/// when they landed.
+fn observation_system_clear_grounded(mut observers: Query<&mut Observer, Added<Grounded>>) {
+    for mut obs in observers.iter_mut() {
+        obs.observed.clear();
+    }
+}
+
+/// Grounded things are left out, so flyers don't flock with them.
 fn observation_system_update_hashmap(
     mut stuff_to_observe: ResMut<StuffsToObserve>,
-    observables: Query<(&Observer, Entity)>)
+    observables: Query<(&Observer, Entity), Without<Grounded>>)
 {
     for thing in stuff_to_observe.stuff.iter_mut() {
         thing.clear();
@@ -130,4 +295,117 @@ fn observation_system_update_hashmap(
             set.push(entity);
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grid() -> StuffsToObserve {
+        StuffsToObserve::new(Vec3::ZERO, 4, 3, 5, 10.0)
+    }
+
+    #[test]
+    fn hash_uses_all_three_axes() {
+        let grid = grid();
+        assert_eq!(grid.hash(Vec3::new(5.0, 5.0, 5.0)), 0);
+        assert_eq!(grid.hash(Vec3::new(15.0, 5.0, 5.0)), 1);
+        assert_eq!(grid.hash(Vec3::new(5.0, 15.0, 5.0)), 4);
+        assert_eq!(grid.hash(Vec3::new(5.0, 5.0, 15.0)), 12);
+    }
+
+    #[test]
+    fn hash_clamps_outside_positions() {
+        let grid = grid();
+        assert_eq!(grid.hash(Vec3::new(-50.0, -50.0, -50.0)), 0);
+        assert_eq!(grid.hash(Vec3::new(500.0, 500.0, 500.0)), grid.stuff.len() - 1);
+    }
+
+    #[test]
+    fn cell_bounds_match_hash() {
+        let grid = StuffsToObserve::new(Vec3::new(-100.0, 0.0, -100.0), 4, 3, 5, 10.0);
+        let position = Vec3::new(-75.0, 12.0, -61.0);
+        let (min, max) = grid.cell_bounds(grid.hash(position));
+        assert_eq!(min, Vec3::new(-80.0, 10.0, -70.0));
+        assert_eq!(max, Vec3::new(-70.0, 20.0, -60.0));
+    }
+
+    #[test]
+    fn hash_respects_origin() {
+        let grid = StuffsToObserve::new(Vec3::new(-100.0, 0.0, -100.0), 4, 3, 5, 10.0);
+        assert_eq!(grid.hash(Vec3::new(-95.0, 5.0, -95.0)), 0);
+    }
+
+    #[test]
+    fn perceives_within_radius() {
+        let obs = Observer {
+            perception_radius: 10.0,
+            ..Default::default()
+        };
+        assert!(obs.perceives(-Vec3::Z, Vec3::new(0.0, 0.0, 9.0)));
+        assert!(!obs.perceives(-Vec3::Z, Vec3::new(0.0, 0.0, -11.0)));
+    }
+
+    #[test]
+    fn perceives_blind_spot() {
+        let obs = Observer {
+            fov_half_angle: PI * 0.75,
+            ..Default::default()
+        };
+        assert!(obs.perceives(-Vec3::Z, Vec3::new(0.0, 0.0, -5.0)));
+        assert!(obs.perceives(-Vec3::Z, Vec3::new(5.0, 0.0, 0.0)));
+        assert!(!obs.perceives(-Vec3::Z, Vec3::new(0.0, 0.0, 5.0)));
+    }
+
+    #[test]
+    fn collect_cells_interior_has_27_neighbours() {
+        let grid = grid();
+        let cell = grid.hash(Vec3::new(15.0, 15.0, 15.0));
+        let cells = grid.collect_cells(cell);
+        assert_eq!(cells.len(), 27);
+        assert!(cells.contains(&cell));
+    }
+
+    #[test]
+    fn collect_cells_corner_has_8_neighbours() {
+        let grid = grid();
+        assert_eq!(grid.collect_cells(0).len(), 8);
+    }
+
+    #[test]
+    fn collect_cells_wraps_around() {
+        let grid = grid().wrapping(40.0, 50.0);
+        let corner = grid.collect_cells(0);
+        assert_eq!(corner.len(), 18);
+        assert!(corner.contains(&grid.hash(Vec3::new(35.0, 5.0, 45.0))));
+        assert!(!corner.contains(&grid.hash(Vec3::new(35.0, 25.0, 45.0))));
+    }
+
+    #[test]
+    fn displacement_wraps_around() {
+        let wrapped = grid().wrapping(40.0, 50.0);
+        assert_eq!(wrapped.displacement(Vec3::new(38.0, 0.0, 2.0), Vec3::new(2.0, 5.0, 48.0)), Vec3::new(4.0, 5.0, -4.0));
+        assert_eq!(grid().displacement(Vec3::new(38.0, 0.0, 2.0), Vec3::new(2.0, 5.0, 48.0)), Vec3::new(-36.0, 5.0, 46.0));
+    }
+
+    #[test]
+    fn collect_cells_excludes_far_layers() {
+        let grid = grid();
+        let bottom = grid.hash(Vec3::new(15.0, 5.0, 15.0));
+        let top = grid.hash(Vec3::new(15.0, 25.0, 15.0));
+        assert!(!grid.collect_cells(bottom).contains(&top));
+    }
+
+    #[test]
+    fn landing_forgets_what_was_observed() {
+        let mut world = World::new();
+        let other = world.spawn().id();
+        let observer = world.spawn()
+            .insert(Observer { observed: vec![other], ..Default::default() })
+            .insert(Grounded)
+            .id();
+
+        SystemStage::single_threaded().with_system(observation_system_clear_grounded).run(&mut world);
+        assert!(world.get::<Observer>(observer).unwrap().observed.is_empty());
+    }
+}
@@ -0,0 +1,303 @@
+use bevy::{
+    prelude::*,
+};
+use crate::flight::*;
+use crate::simulation::*;
+use crate::steering::{SteeringAppExt, SteeringBehaviour};
+
+/// The obstacles plugin. Anything with an `AvoidObstacles` goal looks
+/// ahead for `Obstacle`s and steers around them.
+pub struct Obstacles;
+
+impl Plugin for Obstacles {
+    fn build(&self, app: &mut App) {
+        app
+            .add_system_to_stage(
+                SimulationStage,
+                avoid_obstacles_system
+                    .label(SimulationLabel::Goals)
+                    .after(SimulationLabel::Boids),
+            )
+            .add_steering_behaviour::<AvoidObstacles>();
+    }
+}
+
+/// The shape of an obstacle, relative to its transform's translation
+/// (rotation and scale are ignored).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ObstacleShape {
+    /// Centred on the translation.
+    Sphere { radius: f32 },
+    /// Axis-aligned, centred on the translation.
+    Box { half_extents: Vec3 },
+    /// Upright, standing on the translation (like a tree or a pole).
+    Cylinder { radius: f32, height: f32 },
+}
+
+/// Something static to fly around.
+#[derive(Component, Debug)]
+pub struct Obstacle {
+    pub shape: ObstacleShape,
+}
+
+impl ObstacleShape {
+    /// The middle of the top of the shape, e.g. to perch on.
+    pub fn top(&self, origin: Vec3) -> Vec3 {
+        match *self {
+            ObstacleShape::Sphere { radius } => origin + Vec3::Y * radius,
+            ObstacleShape::Box { half_extents } => origin + Vec3::Y * half_extents.y,
+            ObstacleShape::Cylinder { height, .. } => origin + Vec3::Y * height,
+        }
+    }
+
+    /// How far the point is from the surface (negative if it's inside),
+    /// and the direction out of the shape from there.
+    pub fn distance(&self, origin: Vec3, point: Vec3) -> (f32, Vec3) {
+        let local = point - origin;
+        match *self {
+            ObstacleShape::Sphere { radius } => {
+                let distance = local.length();
+                let normal = if distance > 0.0 { local / distance } else { Vec3::Y };
+                (distance - radius, normal)
+            }
+            ObstacleShape::Box { half_extents } => {
+                let q = local.abs() - half_extents;
+                if q.max_element() > 0.0 {
+                    // Outside: the nearest point is the clamped one.
+                    let outside = q.max(Vec3::ZERO);
+                    let offset = outside * local.signum();
+                    (outside.length(), offset.normalize_or_zero())
+                } else {
+                    // Inside: push out through the nearest face.
+                    let normal = if q.x >= q.y && q.x >= q.z {
+                        Vec3::X * local.x.signum()
+                    } else if q.y >= q.z {
+                        Vec3::Y * local.y.signum()
+                    } else {
+                        Vec3::Z * local.z.signum()
+                    };
+                    (q.max_element(), normal)
+                }
+            }
+            ObstacleShape::Cylinder { radius, height } => {
+                let radial = Vec3::new(local.x, 0.0, local.z);
+                let radial_len = radial.length();
+                let radial_n = if radial_len > 0.0 { radial / radial_len } else { Vec3::X };
+
+                let d_side = radial_len - radius;
+                let d_vert = (local.y - height).max(-local.y);
+                let vert_n = if local.y > height * 0.5 { Vec3::Y } else { -Vec3::Y };
+
+                if d_side > 0.0 && d_vert > 0.0 {
+                    // Off the rim.
+                    let offset = radial_n * d_side + vert_n * d_vert;
+                    (offset.length(), offset.normalize())
+                } else if d_side >= d_vert {
+                    (d_side, radial_n)
+                } else {
+                    (d_vert, vert_n)
+                }
+            }
+        }
+    }
+}
+
+/// Spawns an obstacle, without any visuals.
+pub fn spawn_obstacle(commands: &mut Commands, shape: ObstacleShape, position: Vec3) -> Entity {
+    commands
+        .spawn_bundle(SpatialBundle {
+            transform: Transform::from_translation(position),
+            ..Default::default()
+        })
+        .insert(Obstacle { shape })
+        .insert(Name::new("Obstacle"))
+        .id()
+}
+
+/// A mesh to show an obstacle with, and where to put it relative to the
+/// obstacle. Cylinders are drawn as capsules, which is close enough.
+pub fn obstacle_mesh(shape: &ObstacleShape) -> (Mesh, Vec3) {
+    match *shape {
+        ObstacleShape::Sphere { radius } => (
+            Mesh::from(shape::UVSphere {
+                radius,
+                ..Default::default()
+            }),
+            Vec3::ZERO,
+        ),
+        ObstacleShape::Box { half_extents } => {
+            let size = half_extents * 2.0;
+            (Mesh::from(shape::Box::new(size.x, size.y, size.z)), Vec3::ZERO)
+        }
+        ObstacleShape::Cylinder { radius, height } => (
+            Mesh::from(shape::Capsule {
+                radius,
+                depth: (height - radius * 2.0).max(0.0),
+                ..Default::default()
+            }),
+            Vec3::Y * height * 0.5,
+        ),
+    }
+}
+
+/// A goal to steer around obstacles that are ahead of us.
+#[derive(Component, Debug)]
+pub struct AvoidObstacles {
+    pub target_vel: Vec3,
+    pub weight: f32,
+    /// How far ahead to look, in seconds at our current speed.
+    pub look_ahead_secs: f32,
+    /// How much room to leave between us and an obstacle.
+    pub clearance: f32,
+    /// An obstacle we mean to get close to (e.g. to land on), so we
+    /// don't steer around it.
+    pub ignore: Option<Entity>,
+}
+
+impl SteeringBehaviour for AvoidObstacles {
+    fn steer(&self) -> Vec3 {
+        self.target_vel
+    }
+
+    fn weight(&self) -> f32 {
+        self.weight
+    }
+}
+
+/// How many points along the look-ahead we check.
+const PROBE_COUNT: usize = 4;
+
+/// Probes ahead along our direction of travel for obstacles. Returns the
+/// direction to get out of the way and how urgent it is (0 to 1), with
+/// nearer hits along the probe being more urgent.
+pub fn probe_obstacles<'a>(
+    position: Vec3,
+    forward: Vec3,
+    look_ahead: f32,
+    clearance: f32,
+    obstacles: impl Iterator<Item=(Vec3, &'a ObstacleShape)> + Clone,
+) -> Option<(Vec3, f32)> {
+    for i in 0..=PROBE_COUNT {
+        let along = i as f32 / PROBE_COUNT as f32;
+        let probe = position + forward * look_ahead * along;
+
+        let mut nearest: Option<(f32, Vec3)> = None;
+        for (origin, shape) in obstacles.clone() {
+            let (distance, normal) = shape.distance(origin, probe);
+            if distance < clearance && nearest.map_or(true, |(nearest_dist, _)| distance < nearest_dist) {
+                nearest = Some((distance, normal));
+            }
+        }
+
+        if let Some((distance, normal)) = nearest {
+            let closeness = (1.0 - distance / clearance).clamp(0.0, 1.0);
+            let urgency = (1.0 - along * 0.5) * closeness.max(0.25);
+            return Some((normal, urgency));
+        }
+    }
+    None
+}
+
+/// The system which updates the target velocity and weight on AvoidObstacles.
+fn avoid_obstacles_system(
+    mut query: Query<(&Transform, &Flyer, &mut AvoidObstacles)>,
+    query_obstacles: Query<(Entity, &Transform, &Obstacle)>,
+) {
+    // Query iterators can't be cloned, and we go through these once per probe.
+    let all_obstacles: Vec<(Entity, Vec3, &ObstacleShape)> = query_obstacles.iter()
+        .map(|(entity, obstacle_transform, obstacle)| (entity, obstacle_transform.translation, &obstacle.shape))
+        .collect();
+
+    for (transform, flyer, mut avoid) in query.iter_mut() {
+        avoid.target_vel = Vec3::ZERO;
+        avoid.weight = 0.0;
+
+        let forward = transform.forward();
+        let look_ahead = flyer.speed_linear * avoid.look_ahead_secs;
+        let ignore = avoid.ignore;
+        let obstacles = all_obstacles.iter()
+            .filter(move |(entity, _, _)| Some(*entity) != ignore)
+            .map(|(_, origin, shape)| (*origin, *shape));
+
+        if let Some((normal, urgency)) = probe_obstacles(transform.translation, forward, look_ahead, avoid.clearance, obstacles) {
+            // Steer sideways rather than straight back the way we came.
+            let mut lateral = normal - forward * normal.dot(forward);
+            if lateral.length_squared() < 0.0001 {
+                // Head on, so pick a side.
+                lateral = transform.right();
+            }
+            avoid.target_vel = lateral.normalize() * flyer.props.spd_max;
+            avoid.weight = urgency;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn approx_eq(one: f32, two: f32) -> bool {
+        (one - two).abs() < 0.0001
+    }
+
+    #[test]
+    fn sphere_distance() {
+        let sphere = ObstacleShape::Sphere { radius: 5.0 };
+        let (distance, normal) = sphere.distance(Vec3::ZERO, Vec3::new(8.0, 0.0, 0.0));
+        assert!(approx_eq(distance, 3.0));
+        assert_eq!(normal, Vec3::X);
+
+        let (distance, _) = sphere.distance(Vec3::ZERO, Vec3::new(1.0, 0.0, 0.0));
+        assert!(approx_eq(distance, -4.0));
+    }
+
+    #[test]
+    fn box_distance() {
+        let cube = ObstacleShape::Box { half_extents: Vec3::ONE * 2.0 };
+        let (distance, normal) = cube.distance(Vec3::ZERO, Vec3::new(0.0, 0.0, -5.0));
+        assert!(approx_eq(distance, 3.0));
+        assert_eq!(normal, -Vec3::Z);
+
+        let (distance, normal) = cube.distance(Vec3::ZERO, Vec3::new(0.0, 1.5, 0.0));
+        assert!(approx_eq(distance, -0.5));
+        assert_eq!(normal, Vec3::Y);
+    }
+
+    #[test]
+    fn cylinder_distance() {
+        let pole = ObstacleShape::Cylinder { radius: 1.0, height: 10.0 };
+        let (distance, normal) = pole.distance(Vec3::ZERO, Vec3::new(4.0, 5.0, 0.0));
+        assert!(approx_eq(distance, 3.0));
+        assert_eq!(normal, Vec3::X);
+
+        let (distance, normal) = pole.distance(Vec3::ZERO, Vec3::new(0.0, 12.0, 0.0));
+        assert!(approx_eq(distance, 2.0));
+        assert_eq!(normal, Vec3::Y);
+    }
+
+    #[test]
+    fn tops() {
+        let origin = Vec3::new(10.0, 0.0, 10.0);
+        assert_eq!(ObstacleShape::Sphere { radius: 5.0 }.top(origin), Vec3::new(10.0, 5.0, 10.0));
+        assert_eq!(ObstacleShape::Box { half_extents: Vec3::ONE * 2.0 }.top(origin), Vec3::new(10.0, 2.0, 10.0));
+        assert_eq!(ObstacleShape::Cylinder { radius: 1.0, height: 30.0 }.top(origin), Vec3::new(10.0, 30.0, 10.0));
+    }
+
+    #[test]
+    fn probe_finds_obstacle_ahead() {
+        let pole = ObstacleShape::Cylinder { radius: 2.0, height: 100.0 };
+        let obstacles = [(Vec3::new(1.0, 0.0, -20.0), &pole)];
+        let hit = probe_obstacles(Vec3::new(0.0, 10.0, 0.0), -Vec3::Z, 40.0, 5.0, obstacles.iter().copied());
+        let (normal, urgency) = hit.unwrap();
+        assert!(normal.x < 0.0);
+        assert!(urgency > 0.0);
+    }
+
+    #[test]
+    fn probe_ignores_obstacle_behind() {
+        let pole = ObstacleShape::Cylinder { radius: 2.0, height: 100.0 };
+        let obstacles = [(Vec3::new(0.0, 0.0, 20.0), &pole)];
+        let hit = probe_obstacles(Vec3::new(0.0, 10.0, 0.0), -Vec3::Z, 40.0, 5.0, obstacles.iter().copied());
+        assert!(hit.is_none());
+    }
+}
@@ -0,0 +1,268 @@
+use std::f32::consts::TAU;
+
+use bevy::{
+    prelude::*,
+    gltf::Gltf,
+};
+use rand::prelude::*;
+use crate::anim::*;
+use crate::bounds::Bounds;
+use crate::flight::*;
+use crate::jay_math;
+use crate::scenario::PredatorConfig;
+use crate::simulation::*;
+use crate::steering::{SteeringAppExt, SteeringBehaviour};
+use crate::velocitator::Velocitator;
+
+/// The predators plugin. Predators chase the nearest prey, and prey with
+/// a `Flee` goal try to get away from any predators they can see.
+pub struct Predators;
+
+impl Plugin for Predators {
+    fn build(&self, app: &mut App) {
+        app
+            .add_system_set_to_stage(
+                SimulationStage,
+                SystemSet::new()
+                    .label(SimulationLabel::Goals)
+                    .after(SimulationLabel::Boids)
+                    .with_system(flee_system)
+                    .with_system(predator_chase_system),
+            )
+            .add_steering_behaviour::<Flee>();
+    }
+}
+
+/// Something which hunts anything with a `Flee` goal.
+#[derive(Component, Debug)]
+pub struct Predator {
+    /// What we're currently chasing.
+    pub target: Option<Entity>,
+}
+
+/// A goal to get away from nearby predators. The weight goes from 0 (no
+/// predators in sight) to 1 (a predator right on top of us).
+#[derive(Component, Debug)]
+pub struct Flee {
+    pub target_vel: Vec3,
+    pub weight: f32,
+    /// How close a predator has to be before we flee.
+    pub radius: f32,
+}
+
+impl SteeringBehaviour for Flee {
+    fn steer(&self) -> Vec3 {
+        self.target_vel
+    }
+
+    fn weight(&self) -> f32 {
+        self.weight
+    }
+}
+
+/// Works out which way to flee, given the displacement of each predator
+/// from us. Returns a direction (not normalised) and how urgent it is.
+pub fn flee_direction(radius: f32, predator_displacements: impl Iterator<Item=Vec3>) -> (Vec3, f32) {
+    let mut away = Vec3::ZERO;
+    let mut urgency: f32 = 0.0;
+
+    for displacement in predator_displacements {
+        let distance = displacement.length();
+        if distance >= radius || distance <= 0.0 {
+            continue;
+        }
+
+        let t = 1.0 - distance / radius;
+        away -= displacement / distance * t;
+        urgency = urgency.max(t);
+    }
+
+    (away, urgency)
+}
+
+/// The way to go, which might be across a wrapped edge of the bounds.
+fn displacement_to(bounds: &Option<Res<Bounds>>, from: Vec3, to: Vec3) -> Vec3 {
+    match bounds {
+        Some(bounds) => bounds.displacement(from, to),
+        None => to - from,
+    }
+}
+
+/// The system which updates the target velocity and weight on Flee.
+fn flee_system(
+    mut query: Query<(&Transform, &Flyer, &mut Flee)>,
+    query_predators: Query<&Transform, With<Predator>>,
+    bounds: Option<Res<Bounds>>,
+) {
+    for (transform, flyer, mut flee) in query.iter_mut() {
+        let (away, urgency) = flee_direction(
+            flee.radius,
+            query_predators.iter().map(|predator_transform| displacement_to(&bounds, transform.translation, predator_transform.translation)),
+        );
+
+        if urgency > 0.0 && away.length_squared() > 0.0 {
+            let vel = flyer.speed_linear * transform.forward();
+            flee.target_vel = away.normalize() * flyer.props.spd_max - vel;
+            flee.weight = urgency;
+        } else {
+            flee.target_vel = Vec3::ZERO;
+            flee.weight = 0.0;
+        }
+    }
+}
+
+/// Points each predator at the nearest prey, flat out. Anything that's
+/// landed can't get away, so isn't worth chasing.
+fn predator_chase_system(
+    mut query: Query<(&Transform, &mut Flyer, &mut Predator)>,
+    query_prey: Query<(&Transform, Entity), (With<Flee>, Without<Predator>, Without<Grounded>)>,
+    bounds: Option<Res<Bounds>>,
+) {
+    for (transform, mut flyer, mut predator) in query.iter_mut() {
+        let mut nearest: Option<(f32, Vec3, Entity)> = None;
+        for (prey_transform, prey) in query_prey.iter() {
+            let displacement = displacement_to(&bounds, transform.translation, prey_transform.translation);
+            let dist_sqr = displacement.length_squared();
+            if nearest.map_or(true, |(nearest_sqr, _, _)| dist_sqr < nearest_sqr) {
+                nearest = Some((dist_sqr, displacement, prey));
+            }
+        }
+
+        predator.target = nearest.map(|(_, _, prey)| prey);
+        if let Some((_, displacement, _)) = nearest {
+            if displacement.length_squared() > 0.0 {
+                flyer.goal_velocity = displacement.normalize() * flyer.props.spd_max;
+            }
+        }
+    }
+}
+
+/// Spawns all of a kind of predator at random positions in its spawn region.
+pub fn spawn_predators(
+    commands: &mut Commands,
+    rng: &mut impl Rng,
+    bounds: &Bounds,
+    config: &PredatorConfig,
+    model: Option<Handle<Gltf>>,
+) {
+    let (min, max) = config.spawn_box(bounds);
+
+    for _ in 0..config.count {
+        let position = Vec3::new(
+            rng.gen_range(min[0]..=max[0]),
+            rng.gen_range(min[1]..=max[1]),
+            rng.gen_range(min[2]..=max[2]),
+        );
+        let rotation = Quat::from_axis_angle(Vec3::Y, rng.gen_range(-TAU..TAU) * 0.5);
+
+        make_predator(commands, config, model.clone(), position, rotation);
+    }
+}
+
+/// Makes a single predator, which flies with the same model as the crows.
+pub fn make_predator(
+    commands: &mut Commands,
+    config: &PredatorConfig,
+    model: Option<Handle<Gltf>>,
+    position: Vec3,
+    rotation: Quat,
+) -> Entity {
+    let spd = config.initial_speed;
+    let fwd = rotation * -Vec3::Z;
+    let (yaw, pitch) = jay_math::vec3_to_yaw_pitch(fwd);
+
+    let transform = Transform {
+        translation: position,
+        rotation,
+        scale: Vec3::ONE * config.scale,
+    };
+
+    let mut predator = commands.spawn_bundle((
+        Name::new(config.name.clone()),
+        Predator {
+            target: None,
+        },
+        Flyer {
+            speed_linear: spd,
+            ang_x: pitch,
+            ang_y: yaw,
+            goal_velocity: fwd * spd,
+            props: config.flight.to_props(),
+            ..Default::default()
+        },
+        Velocitator {
+            velocity: fwd * spd,
+        },
+        Interpolated::new(transform),
+    ));
+    predator.insert_bundle(
+        SpatialBundle {
+            transform,
+            ..Default::default()
+        });
+
+    if let Some(handle) = model {
+        predator.insert_bundle((
+            ModelGLTF {
+                handle,
+            },
+            ModelWaitingToSpawn {},
+            StartAnim {
+                name: String::from("Flap"),
+                loop_plz: true,
+                pose_at: None,
+            },
+        ));
+    }
+
+    predator.id()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flee_ignores_far_predators() {
+        let (away, urgency) = flee_direction(50.0, [Vec3::new(60.0, 0.0, 0.0)].into_iter());
+        assert_eq!(away, Vec3::ZERO);
+        assert_eq!(urgency, 0.0);
+    }
+
+    #[test]
+    fn flee_away_from_near_predators() {
+        let (away, urgency) = flee_direction(50.0, [Vec3::new(10.0, 0.0, 0.0)].into_iter());
+        assert!(away.x < 0.0);
+        assert!((urgency - 0.8).abs() < 0.0001);
+    }
+
+    #[test]
+    fn flee_more_urgent_when_closer() {
+        let (_, near) = flee_direction(50.0, [Vec3::new(5.0, 0.0, 0.0)].into_iter());
+        let (_, far) = flee_direction(50.0, [Vec3::new(40.0, 0.0, 0.0)].into_iter());
+        assert!(near > far);
+    }
+
+    fn prey(world: &mut World, position: Vec3) -> Entity {
+        world.spawn()
+            .insert(Transform::from_translation(position))
+            .insert(Flee { target_vel: Vec3::ZERO, weight: 0.0, radius: 50.0 })
+            .id()
+    }
+
+    #[test]
+    fn predators_ignore_grounded_prey() {
+        let mut world = World::new();
+        let predator = world.spawn()
+            .insert(Transform::default())
+            .insert(Flyer::default())
+            .insert(Predator { target: None })
+            .id();
+        let grounded = prey(&mut world, Vec3::new(10.0, 0.0, 0.0));
+        world.entity_mut(grounded).insert(Grounded);
+        let flying = prey(&mut world, Vec3::new(0.0, 0.0, 40.0));
+
+        SystemStage::single_threaded().with_system(predator_chase_system).run(&mut world);
+        assert_eq!(world.get::<Predator>(predator).unwrap().target, Some(flying));
+    }
+}
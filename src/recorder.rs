@@ -0,0 +1,583 @@
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use bevy::{
+    prelude::*,
+    utils::HashMap,
+};
+use big_brain::prelude::*;
+use crate::crows::{Flap, Forage, Land, Stamina};
+use crate::flight::Flyer;
+use crate::simulation::*;
+use crate::velocitator::Velocitator;
+
+/// The recorder plugin. While the `Recording` resource is recording,
+/// every `Flyer` is sampled at the end of each tick and written out as CSV
+/// or in a compact binary format (see `write_binary_frame`). The header,
+/// with each flyer's scale, is written along with the first tick.
+pub struct Recorder;
+
+impl Plugin for Recorder {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<Recording>()
+            .add_system_to_stage(
+                SimulationStage,
+                record_system
+                    .label(SimulationLabel::Record)
+                    .after(SimulationLabel::Flight),
+            );
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RecordFormat {
+    /// One line per flyer per tick, under `CSV_HEADER` (and the tick rate
+    /// and scales, see `write_csv_header`).
+    #[default]
+    Csv,
+    /// Little-endian, starting with `BINARY_MAGIC`, the tick rate and
+    /// scales. See `write_binary_header` and `write_binary_frame`.
+    Binary,
+}
+
+impl RecordFormat {
+    pub const ALL: [RecordFormat; 2] = [RecordFormat::Csv, RecordFormat::Binary];
+
+    /// CSV for `.csv` files, binary for anything else.
+    pub fn from_path(path: impl AsRef<Path>) -> RecordFormat {
+        match path.as_ref().extension() {
+            Some(extension) if extension.eq_ignore_ascii_case("csv") => RecordFormat::Csv,
+            _ => RecordFormat::Binary,
+        }
+    }
+}
+
+/// Which of its `Thinker`'s actions a flyer was carrying out.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecordedAction {
+    Nothing,
+    Flap,
+    Land,
+    Forage,
+}
+
+impl RecordedAction {
+    pub const ALL: [RecordedAction; 4] = [RecordedAction::Nothing, RecordedAction::Flap, RecordedAction::Land, RecordedAction::Forage];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            RecordedAction::Nothing => "none",
+            RecordedAction::Flap => "flap",
+            RecordedAction::Land => "land",
+            RecordedAction::Forage => "forage",
+        }
+    }
+
+    /// How it's stored in the binary format.
+    pub fn code(self) -> u8 {
+        self as u8
+    }
+
+    pub fn from_code(code: u8) -> Option<RecordedAction> {
+        RecordedAction::ALL.get(code as usize).copied()
+    }
+
+    pub fn from_name(name: &str) -> Option<RecordedAction> {
+        RecordedAction::ALL.into_iter().find(|action| action.name() == name)
+    }
+}
+
+/// One flyer at one tick.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sample {
+    /// The flyer's `Entity::to_bits`, to match up its samples across ticks.
+    pub id: u64,
+    pub position: Vec3,
+    pub velocity: Vec3,
+    pub yaw: f32,
+    pub pitch: f32,
+    /// Only for things with `Stamina`.
+    pub stamina: Option<f32>,
+    pub action: RecordedAction,
+}
+
+/// Every flyer at one tick, in order of id.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Frame {
+    pub tick: u64,
+    pub samples: Vec<Sample>,
+}
+
+/// A whole recording, read back in.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RecordedRun {
+    /// How many ticks per second it was recorded at.
+    pub tick_rate: f64,
+    /// Each flyer's scale, by id.
+    pub scales: BTreeMap<u64, f32>,
+    pub frames: Vec<Frame>,
+}
+
+pub const CSV_HEADER: &str = "tick,id,x,y,z,vx,vy,vz,yaw,pitch,stamina,action";
+
+/// What comes before the tick rate on the first line.
+pub const CSV_TICK_RATE: &str = "# tick_rate=";
+
+/// What comes before the scales on the line above `CSV_HEADER`.
+pub const CSV_SCALES: &str = "# scales=";
+
+/// The start of every binary recording.
+pub const BINARY_MAGIC: &[u8; 8] = b"BOIDREC1";
+
+/// Starts a CSV recording: a comment with the tick rate, another with each
+/// flyer's `id:scale` (separated by spaces), then `CSV_HEADER`.
+pub fn write_csv_header(writer: &mut impl Write, tick_rate: f64, scales: &BTreeMap<u64, f32>) -> io::Result<()> {
+    writeln!(writer, "{}{}", CSV_TICK_RATE, tick_rate)?;
+    let scales: Vec<String> = scales.iter().map(|(id, scale)| format!("{}:{}", id, scale)).collect();
+    writeln!(writer, "{}{}", CSV_SCALES, scales.join(" "))?;
+    writeln!(writer, "{}", CSV_HEADER)
+}
+
+/// Starts a binary recording: `BINARY_MAGIC`, the tick rate (f64), the
+/// number of scales (u32), then each flyer's id (u64) and scale (f32).
+pub fn write_binary_header(writer: &mut impl Write, tick_rate: f64, scales: &BTreeMap<u64, f32>) -> io::Result<()> {
+    writer.write_all(BINARY_MAGIC)?;
+    writer.write_all(&tick_rate.to_le_bytes())?;
+    writer.write_all(&(scales.len() as u32).to_le_bytes())?;
+    for (id, scale) in scales.iter() {
+        writer.write_all(&id.to_le_bytes())?;
+        writer.write_all(&scale.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+/// Writes a frame as CSV lines, leaving the stamina empty if there isn't any.
+pub fn write_csv_frame(writer: &mut impl Write, frame: &Frame) -> io::Result<()> {
+    for sample in frame.samples.iter() {
+        let stamina = sample.stamina.map_or(String::new(), |stamina| stamina.to_string());
+        writeln!(
+            writer,
+            "{},{},{},{},{},{},{},{},{},{},{},{}",
+            frame.tick,
+            sample.id,
+            sample.position.x,
+            sample.position.y,
+            sample.position.z,
+            sample.velocity.x,
+            sample.velocity.y,
+            sample.velocity.z,
+            sample.yaw,
+            sample.pitch,
+            stamina,
+            sample.action.name(),
+        )?;
+    }
+    Ok(())
+}
+
+/// Writes a frame in the binary format: the tick (u64) and the number of
+/// samples (u32), then for each sample its id (u64), position, velocity,
+/// yaw, pitch and stamina (f32s, with NaN for no stamina) and action (u8).
+pub fn write_binary_frame(writer: &mut impl Write, frame: &Frame) -> io::Result<()> {
+    writer.write_all(&frame.tick.to_le_bytes())?;
+    writer.write_all(&(frame.samples.len() as u32).to_le_bytes())?;
+    for sample in frame.samples.iter() {
+        writer.write_all(&sample.id.to_le_bytes())?;
+        let floats = [
+            sample.position.x,
+            sample.position.y,
+            sample.position.z,
+            sample.velocity.x,
+            sample.velocity.y,
+            sample.velocity.z,
+            sample.yaw,
+            sample.pitch,
+            sample.stamina.unwrap_or(f32::NAN),
+        ];
+        for float in floats {
+            writer.write_all(&float.to_le_bytes())?;
+        }
+        writer.write_all(&[sample.action.code()])?;
+    }
+    Ok(())
+}
+
+fn read_bytes<const N: usize>(reader: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn read_f32(reader: &mut impl Read) -> io::Result<f32> {
+    Ok(f32::from_le_bytes(read_bytes(reader)?))
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn check_tick_rate(tick_rate: f64) -> io::Result<f64> {
+    if tick_rate.is_finite() && tick_rate > 0.0 {
+        Ok(tick_rate)
+    } else {
+        Err(invalid_data("bad tick rate in recording"))
+    }
+}
+
+/// Reads a whole binary recording back in.
+pub fn read_binary(mut reader: impl Read) -> io::Result<RecordedRun> {
+    if &read_bytes::<8>(&mut reader)? != BINARY_MAGIC {
+        return Err(invalid_data("not a binary recording"));
+    }
+    let tick_rate = check_tick_rate(f64::from_le_bytes(read_bytes(&mut reader)?))?;
+    let mut scales = BTreeMap::new();
+    for _ in 0..u32::from_le_bytes(read_bytes(&mut reader)?) {
+        let id = u64::from_le_bytes(read_bytes(&mut reader)?);
+        scales.insert(id, read_f32(&mut reader)?);
+    }
+
+    let mut frames = Vec::new();
+    loop {
+        let tick = match read_bytes::<8>(&mut reader) {
+            Ok(bytes) => u64::from_le_bytes(bytes),
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(err) => return Err(err),
+        };
+        let count = u32::from_le_bytes(read_bytes(&mut reader)?);
+
+        let mut samples = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let id = u64::from_le_bytes(read_bytes(&mut reader)?);
+            let position = Vec3::new(read_f32(&mut reader)?, read_f32(&mut reader)?, read_f32(&mut reader)?);
+            let velocity = Vec3::new(read_f32(&mut reader)?, read_f32(&mut reader)?, read_f32(&mut reader)?);
+            let yaw = read_f32(&mut reader)?;
+            let pitch = read_f32(&mut reader)?;
+            let stamina = read_f32(&mut reader)?;
+            let action = RecordedAction::from_code(read_bytes::<1>(&mut reader)?[0])
+                .ok_or_else(|| invalid_data("unknown action"))?;
+            samples.push(Sample {
+                id,
+                position,
+                velocity,
+                yaw,
+                pitch,
+                stamina: if stamina.is_nan() { None } else { Some(stamina) },
+                action,
+            });
+        }
+        frames.push(Frame { tick, samples });
+    }
+    Ok(RecordedRun { tick_rate, scales, frames })
+}
+
+/// Reads a whole CSV recording back in. Ticks without any samples don't
+/// make it into CSV, so there won't be any empty frames.
+pub fn read_csv(reader: impl BufRead) -> io::Result<RecordedRun> {
+    let mut lines = reader.lines();
+    let tick_rate = lines.next().transpose()?;
+    let tick_rate = tick_rate.as_deref()
+        .and_then(|line| line.trim().strip_prefix(CSV_TICK_RATE))
+        .and_then(|tick_rate| tick_rate.parse().ok())
+        .ok_or_else(|| invalid_data("not a CSV recording"))?;
+    let tick_rate = check_tick_rate(tick_rate)?;
+    let scales = lines.next().transpose()?;
+    let scales = scales.as_deref()
+        .and_then(|line| line.trim().strip_prefix(CSV_SCALES))
+        .and_then(parse_csv_scales)
+        .ok_or_else(|| invalid_data("bad scales in CSV recording"))?;
+    let header = lines.next().transpose()?;
+    if header.as_deref().map(str::trim) != Some(CSV_HEADER) {
+        return Err(invalid_data("not a CSV recording"));
+    }
+
+    let mut frames: Vec<Frame> = Vec::new();
+    for line in lines {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (tick, sample) = parse_csv_line(line.trim())
+            .ok_or_else(|| invalid_data(&format!("bad line in CSV recording: {}", line)))?;
+        match frames.last_mut() {
+            Some(frame) if frame.tick == tick => frame.samples.push(sample),
+            _ => frames.push(Frame { tick, samples: vec![sample] }),
+        }
+    }
+    Ok(RecordedRun { tick_rate, scales, frames })
+}
+
+fn parse_csv_scales(scales: &str) -> Option<BTreeMap<u64, f32>> {
+    scales.split_whitespace()
+        .map(|scale| {
+            let (id, scale) = scale.split_once(':')?;
+            Some((id.parse().ok()?, scale.parse().ok()?))
+        })
+        .collect()
+}
+
+fn parse_csv_line(line: &str) -> Option<(u64, Sample)> {
+    let fields: Vec<&str> = line.split(',').collect();
+    if fields.len() != CSV_HEADER.split(',').count() {
+        return None;
+    }
+    let float = |index: usize| fields[index].parse::<f32>().ok();
+
+    let sample = Sample {
+        id: fields[1].parse().ok()?,
+        position: Vec3::new(float(2)?, float(3)?, float(4)?),
+        velocity: Vec3::new(float(5)?, float(6)?, float(7)?),
+        yaw: float(8)?,
+        pitch: float(9)?,
+        stamina: if fields[10].is_empty() { None } else { Some(float(10)?) },
+        action: RecordedAction::from_name(fields[11])?,
+    };
+    Some((fields[0].parse().ok()?, sample))
+}
+
+/// Reads a recording from a file, in whichever format its name says it's in
+/// (see `RecordFormat::from_path`).
+pub fn read_recording(path: impl AsRef<Path>) -> io::Result<RecordedRun> {
+    let reader = BufReader::new(File::open(path.as_ref())?);
+    match RecordFormat::from_path(path) {
+        RecordFormat::Csv => read_csv(reader),
+        RecordFormat::Binary => read_binary(reader),
+    }
+}
+
+struct RecordingFile {
+    path: PathBuf,
+    format: RecordFormat,
+    writer: BufWriter<File>,
+    /// Until the header's been written.
+    tick_rate: Option<f64>,
+    frames: u64,
+}
+
+impl RecordingFile {
+    fn write_header(&mut self, scales: &BTreeMap<u64, f32>) -> io::Result<()> {
+        if let Some(tick_rate) = self.tick_rate.take() {
+            match self.format {
+                RecordFormat::Csv => write_csv_header(&mut self.writer, tick_rate, scales)?,
+                RecordFormat::Binary => write_binary_header(&mut self.writer, tick_rate, scales)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The recording in progress, if there is one.
+#[derive(Default)]
+pub struct Recording {
+    file: Option<RecordingFile>,
+}
+
+impl Recording {
+    /// Starts recording to a new file, stopping any recording already going.
+    /// The tick rate goes in the file, for playing it back at the right speed.
+    pub fn start(&mut self, path: impl AsRef<Path>, format: RecordFormat, tick_rate: f64) -> io::Result<()> {
+        self.stop()?;
+
+        self.file = Some(RecordingFile {
+            path: path.as_ref().to_path_buf(),
+            format,
+            writer: BufWriter::new(File::create(path.as_ref())?),
+            tick_rate: Some(tick_rate),
+            frames: 0,
+        });
+        Ok(())
+    }
+
+    /// Stops recording, making sure everything's been written.
+    pub fn stop(&mut self) -> io::Result<()> {
+        match self.file.take() {
+            Some(mut file) => {
+                // Nothing was recorded, but it should still be readable.
+                file.write_header(&BTreeMap::new())?;
+                file.writer.flush()
+            }
+            None => Ok(()),
+        }
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.file.is_some()
+    }
+
+    /// Where we're recording to.
+    pub fn path(&self) -> Option<&Path> {
+        self.file.as_ref().map(|file| file.path.as_path())
+    }
+
+    /// How many ticks we've recorded so far.
+    pub fn frames(&self) -> u64 {
+        self.file.as_ref().map_or(0, |file| file.frames)
+    }
+
+    fn needs_header(&self) -> bool {
+        self.file.as_ref().map_or(false, |file| file.tick_rate.is_some())
+    }
+
+    /// Writes a tick, after the header with the given scales if this is the first.
+    fn write(&mut self, frame: &Frame, scales: &BTreeMap<u64, f32>) -> io::Result<()> {
+        if let Some(file) = &mut self.file {
+            file.write_header(scales)?;
+            match file.format {
+                RecordFormat::Csv => write_csv_frame(&mut file.writer, frame)?,
+                RecordFormat::Binary => write_binary_frame(&mut file.writer, frame)?,
+            }
+            file.frames += 1;
+        }
+        Ok(())
+    }
+}
+
+/// Samples every flyer into a frame and writes it, if we're recording.
+fn record_system(
+    time: Res<SimulationTime>,
+    mut recording: ResMut<Recording>,
+    mut frame: Local<Frame>,
+    query: Query<(Entity, &Transform, &Flyer, Option<&Velocitator>, Option<&Stamina>)>,
+    query_actions: Query<(&Actor, &ActionState, Option<&Flap>, Option<&Land>, Option<&Forage>)>,
+) {
+    if !recording.is_recording() {
+        return;
+    }
+
+    let actions: HashMap<Entity, RecordedAction> = query_actions.iter()
+        .filter(|(_, state, ..)| matches!(state, ActionState::Requested | ActionState::Executing))
+        .filter_map(|(Actor(actor), _, flap, land, forage)| {
+            let action = if flap.is_some() {
+                RecordedAction::Flap
+            } else if land.is_some() {
+                RecordedAction::Land
+            } else if forage.is_some() {
+                RecordedAction::Forage
+            } else {
+                return None;
+            };
+            Some((*actor, action))
+        })
+        .collect();
+
+    frame.tick = time.tick();
+    frame.samples.clear();
+    frame.samples.extend(query.iter().map(|(entity, transform, flyer, velocitator, stamina)| Sample {
+        id: entity.to_bits(),
+        position: transform.translation,
+        velocity: velocitator.map_or(flyer.speed_linear * transform.forward(), |velocitator| velocitator.velocity),
+        yaw: flyer.ang_y,
+        pitch: flyer.ang_x,
+        stamina: stamina.map(|stamina| stamina.current_value()),
+        action: actions.get(&entity).copied().unwrap_or(RecordedAction::Nothing),
+    }));
+    frame.samples.sort_by_key(|sample| sample.id);
+
+    // Only the first tick's scales are needed, for the header.
+    let scales = if recording.needs_header() {
+        query.iter().map(|(entity, transform, ..)| (entity.to_bits(), transform.scale.x)).collect()
+    } else {
+        BTreeMap::new()
+    };
+    if let Err(err) = recording.write(&frame, &scales) {
+        eprintln!("Stopped recording: {}", err);
+        recording.file = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame() -> Frame {
+        Frame {
+            tick: 42,
+            samples: vec![
+                Sample {
+                    id: 7,
+                    position: Vec3::new(1.0, 2.5, -3.0),
+                    velocity: Vec3::new(0.0, 0.0, -50.0),
+                    yaw: 0.5,
+                    pitch: -0.25,
+                    stamina: Some(75.5),
+                    action: RecordedAction::Flap,
+                },
+                Sample {
+                    id: 9,
+                    position: Vec3::ZERO,
+                    velocity: Vec3::X,
+                    yaw: 0.0,
+                    pitch: 0.0,
+                    stamina: None,
+                    action: RecordedAction::Nothing,
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn csv_lines() {
+        let mut csv = Vec::new();
+        write_csv_frame(&mut csv, &frame()).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines, ["42,7,1,2.5,-3,0,0,-50,0.5,-0.25,75.5,flap", "42,9,0,0,0,1,0,0,0,0,,none"]);
+        assert_eq!(lines[0].split(',').count(), CSV_HEADER.split(',').count());
+    }
+
+    fn scales() -> BTreeMap<u64, f32> {
+        BTreeMap::from([(7, 0.1), (9, 0.18)])
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        let mut binary = Vec::new();
+        write_binary_header(&mut binary, 30.0, &scales()).unwrap();
+        write_binary_frame(&mut binary, &frame()).unwrap();
+        write_binary_frame(&mut binary, &Frame { tick: 43, samples: Vec::new() }).unwrap();
+        assert_eq!(binary.len(), (20 + 2 * 12) + (12 + 2 * 45) + 12);
+
+        let run = read_binary(binary.as_slice()).unwrap();
+        assert_eq!(run.tick_rate, 30.0);
+        assert_eq!(run.scales, scales());
+        assert_eq!(run.frames, vec![frame(), Frame { tick: 43, samples: Vec::new() }]);
+    }
+
+    #[test]
+    fn csv_round_trip() {
+        let mut csv = Vec::new();
+        write_csv_header(&mut csv, 120.0, &scales()).unwrap();
+        write_csv_frame(&mut csv, &frame()).unwrap();
+        write_csv_frame(&mut csv, &Frame { tick: 43, ..frame() }).unwrap();
+
+        let run = read_csv(csv.as_slice()).unwrap();
+        assert_eq!(run.tick_rate, 120.0);
+        assert_eq!(run.scales, scales());
+        assert_eq!(run.frames, vec![frame(), Frame { tick: 43, ..frame() }]);
+        assert!(read_csv(&b"tick,id\n"[..]).is_err());
+        assert!(read_csv(format!("{}\n", CSV_HEADER).as_bytes()).is_err());
+        assert!(read_csv(format!("{}60\n{}\n", CSV_TICK_RATE, CSV_HEADER).as_bytes()).is_err());
+        assert!(read_csv(format!("{}60\n{}7\n{}\n", CSV_TICK_RATE, CSV_SCALES, CSV_HEADER).as_bytes()).is_err());
+        assert!(read_csv(format!("{}60\n{}\n{}\n42,7,1,2\n", CSV_TICK_RATE, CSV_SCALES, CSV_HEADER).as_bytes()).is_err());
+    }
+
+    #[test]
+    fn binary_needs_magic_and_tick_rate() {
+        assert!(read_binary(&b"NOTBOIDS"[..]).is_err());
+        assert!(read_binary(&BINARY_MAGIC[..]).is_err());
+        let mut binary = Vec::new();
+        write_binary_header(&mut binary, 0.0, &BTreeMap::new()).unwrap();
+        assert!(read_binary(binary.as_slice()).is_err());
+    }
+
+    #[test]
+    fn format_from_extension() {
+        assert_eq!(RecordFormat::from_path("flock.csv"), RecordFormat::Csv);
+        assert_eq!(RecordFormat::from_path("flock.CSV"), RecordFormat::Csv);
+        assert_eq!(RecordFormat::from_path("flock.rec"), RecordFormat::Binary);
+    }
+}
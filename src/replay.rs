@@ -0,0 +1,280 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use bevy::{
+    gltf::Gltf,
+    prelude::*,
+};
+use crate::anim::*;
+use crate::jay_math;
+use crate::recorder::*;
+
+/// The replay plugin. Plays back a recording (see `recorder`) by putting
+/// each `ReplayTrack` wherever its flyer was, rather than simulating
+/// anything. Needs a `ReplayFrames` resource; `Playback` controls where we
+/// are in it.
+pub struct Replay;
+
+impl Plugin for Replay {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<Playback>()
+            .add_system(playback_system)
+            .add_system(replay_track_system.after(playback_system));
+    }
+}
+
+/// Where we are in the replay, and how fast we're going through it.
+pub struct Playback {
+    /// The tick we're showing, which is usually between two recorded ones.
+    pub tick: f64,
+    pub playing: bool,
+    /// 1 is as fast as it was recorded.
+    pub speed: f32,
+    /// How many ticks per second the recording was made at.
+    pub tick_rate: f64,
+}
+
+impl Default for Playback {
+    fn default() -> Self {
+        Playback {
+            tick: 0.0,
+            playing: true,
+            speed: 1.0,
+            tick_rate: 60.0,
+        }
+    }
+}
+
+/// A whole recording, loaded for replaying.
+pub struct ReplayFrames {
+    frames: Vec<Frame>,
+    scales: BTreeMap<u64, f32>,
+}
+
+impl ReplayFrames {
+    pub fn new(mut frames: Vec<Frame>, scales: BTreeMap<u64, f32>) -> ReplayFrames {
+        frames.sort_by_key(|frame| frame.tick);
+        for frame in frames.iter_mut() {
+            frame.samples.sort_by_key(|sample| sample.id);
+        }
+        ReplayFrames { frames, scales }
+    }
+
+    /// How big a flyer was drawn, or 1 if the recording doesn't say.
+    pub fn scale(&self, id: u64) -> f32 {
+        self.scales.get(&id).copied().unwrap_or(1.0)
+    }
+
+    pub fn first_tick(&self) -> u64 {
+        self.frames.first().map_or(0, |frame| frame.tick)
+    }
+
+    pub fn last_tick(&self) -> u64 {
+        self.frames.last().map_or(0, |frame| frame.tick)
+    }
+
+    /// The id of every flyer in the recording, in order.
+    pub fn ids(&self) -> Vec<u64> {
+        let ids: BTreeSet<u64> = self.frames.iter()
+            .flat_map(|frame| frame.samples.iter().map(|sample| sample.id))
+            .collect();
+        ids.into_iter().collect()
+    }
+
+    /// What a flyer was doing at the given tick, interpolated between the
+    /// recorded ticks either side. Nothing if it wasn't recorded then.
+    pub fn sample_at(&self, id: u64, tick: f64) -> Option<Sample> {
+        let next = self.frames.partition_point(|frame| (frame.tick as f64) < tick);
+        let after = self.frames.get(next)?;
+        if after.tick as f64 == tick {
+            return find_sample(after, id).cloned();
+        }
+
+        let before = &self.frames[next.checked_sub(1)?];
+        let t = (tick - before.tick as f64) / (after.tick - before.tick) as f64;
+        Some(lerp_sample(find_sample(before, id)?, find_sample(after, id)?, t as f32))
+    }
+}
+
+fn find_sample(frame: &Frame, id: u64) -> Option<&Sample> {
+    frame.samples
+        .binary_search_by_key(&id, |sample| sample.id)
+        .ok()
+        .map(|index| &frame.samples[index])
+}
+
+/// Blends two samples of the same flyer, turning the short way round.
+/// The action is whatever it was doing at the earlier one.
+pub fn lerp_sample(from: &Sample, to: &Sample, t: f32) -> Sample {
+    Sample {
+        id: from.id,
+        position: from.position.lerp(to.position, t),
+        velocity: from.velocity.lerp(to.velocity, t),
+        yaw: from.yaw + jay_math::delta_angle(from.yaw, to.yaw) * t,
+        pitch: jay_math::lerp(from.pitch, to.pitch, t),
+        stamina: match (from.stamina, to.stamina) {
+            (Some(from), Some(to)) => Some(jay_math::lerp(from, to, t)),
+            _ => from.stamina,
+        },
+        action: from.action,
+    }
+}
+
+/// One recorded flyer being played back.
+#[derive(Component, Debug)]
+pub struct ReplayTrack {
+    /// The flyer's id in the recording.
+    pub id: u64,
+    /// What it's doing now, to change its animation to suit.
+    pub action: RecordedAction,
+}
+
+/// Spawns a track for every flyer in the recording, at its recorded scale
+/// and with the given model if there is one. They're hidden until the
+/// replay gets to them.
+pub fn spawn_tracks(commands: &mut Commands, frames: &ReplayFrames, model: Option<Handle<Gltf>>) -> Vec<Entity> {
+    frames.ids()
+        .into_iter()
+        .map(|id| {
+            let mut track = commands.spawn_bundle(SpatialBundle {
+                visibility: Visibility { is_visible: false },
+                transform: Transform::from_scale(Vec3::ONE * frames.scale(id)),
+                ..default()
+            });
+            track.insert_bundle((
+                Name::new(format!("Track {}", id)),
+                ReplayTrack {
+                    id,
+                    action: RecordedAction::Nothing,
+                },
+            ));
+            if let Some(handle) = model.clone() {
+                track.insert_bundle((
+                    ModelGLTF {
+                        handle,
+                    },
+                    ModelWaitingToSpawn {},
+                    StartAnim {
+                        name: String::from("Soar"),
+                        loop_plz: true,
+                        pose_at: None,
+                    },
+                ));
+            }
+            track.id()
+        })
+        .collect()
+}
+
+/// Moves the playback on, stopping at the end.
+fn playback_system(
+    time: Res<Time>,
+    frames: Res<ReplayFrames>,
+    mut playback: ResMut<Playback>,
+) {
+    if playback.playing {
+        playback.tick += time.delta_seconds_f64() * playback.speed as f64 * playback.tick_rate;
+    }
+
+    let (first, last) = (frames.first_tick() as f64, frames.last_tick() as f64);
+    if playback.tick >= last {
+        playback.playing = false;
+    }
+    playback.tick = playback.tick.clamp(first, last);
+}
+
+/// Puts every track where its flyer was, hiding any which weren't around.
+fn replay_track_system(
+    mut commands: Commands,
+    frames: Res<ReplayFrames>,
+    playback: Res<Playback>,
+    mut query: Query<(Entity, &mut ReplayTrack, &mut Transform, &mut Visibility)>,
+) {
+    for (entity, mut track, mut transform, mut visibility) in query.iter_mut() {
+        let sample = match frames.sample_at(track.id, playback.tick) {
+            Some(sample) => sample,
+            None => {
+                visibility.is_visible = false;
+                continue;
+            }
+        };
+
+        visibility.is_visible = true;
+        transform.translation = sample.position;
+        transform.rotation = Quat::from_euler(EulerRot::YXZ, sample.yaw, sample.pitch, 0.0);
+
+        if track.action != sample.action {
+            track.action = sample.action;
+            let anim = if sample.action == RecordedAction::Flap { "Flap" } else { "Soar" };
+            commands.entity(entity).insert(StartAnim {
+                name: String::from(anim),
+                loop_plz: true,
+                pose_at: None,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(id: u64, x: f32, yaw: f32, action: RecordedAction) -> Sample {
+        Sample {
+            id,
+            position: Vec3::new(x, 10.0, 0.0),
+            velocity: Vec3::ZERO,
+            yaw,
+            pitch: 0.0,
+            stamina: None,
+            action,
+        }
+    }
+
+    fn frames() -> ReplayFrames {
+        ReplayFrames::new(vec![
+            Frame { tick: 12, samples: vec![sample(2, 20.0, -3.0, RecordedAction::Flap)] },
+            Frame { tick: 10, samples: vec![sample(2, 0.0, 3.0, RecordedAction::Nothing), sample(1, 5.0, 0.0, RecordedAction::Nothing)] },
+        ], BTreeMap::from([(2, 0.1)]))
+    }
+
+    #[test]
+    fn samples_are_interpolated() {
+        let frames = frames();
+        assert_eq!(frames.first_tick(), 10);
+        assert_eq!(frames.last_tick(), 12);
+        assert_eq!(frames.ids(), vec![1, 2]);
+
+        let halfway = frames.sample_at(2, 11.0).unwrap();
+        assert_eq!(halfway.position, Vec3::new(10.0, 10.0, 0.0));
+        assert_eq!(halfway.action, RecordedAction::Nothing);
+        assert!(halfway.yaw > 3.0, "should turn the short way round, got {}", halfway.yaw);
+
+        assert_eq!(frames.sample_at(2, 12.0).unwrap().action, RecordedAction::Flap);
+        assert_eq!(frames.sample_at(1, 10.0).unwrap().position.x, 5.0);
+    }
+
+    #[test]
+    fn missing_samples() {
+        let frames = frames();
+        assert_eq!(frames.sample_at(1, 11.0), None);
+        assert_eq!(frames.sample_at(2, 9.0), None);
+        assert_eq!(frames.sample_at(2, 13.0), None);
+        assert_eq!(frames.sample_at(3, 10.0), None);
+    }
+
+    #[test]
+    fn tracks_are_spawned_at_their_scale() {
+        let mut world = World::new();
+        let tracks = {
+            let mut queue = bevy::ecs::system::CommandQueue::default();
+            let mut commands = Commands::new(&mut queue, &world);
+            let tracks = spawn_tracks(&mut commands, &frames(), None);
+            queue.apply(&mut world);
+            tracks
+        };
+
+        let scales: Vec<f32> = tracks.iter().map(|track| world.get::<Transform>(*track).unwrap().scale.x).collect();
+        assert_eq!(scales, vec![1.0, 0.1]);
+    }
+}
@@ -0,0 +1,683 @@
+use std::{fmt, fs, path::Path};
+
+use bevy::prelude::Vec3;
+use serde::{Deserialize, Serialize};
+use crate::bounds::{ArenaShape, BoundaryMode, Bounds};
+use crate::crows::CrowGlobalProps;
+use crate::flight::{Aerodynamics, FlyerProps};
+use crate::obstacles::ObstacleShape;
+use crate::terrain::Terrain;
+
+/// Everything needed to set up a flock: the bounds, the global weights
+/// and which species to spawn. Usually loaded from a RON file (see
+/// `assets/scenarios/default.ron`), anything left out of the file takes
+/// the default value, which is the classic crow demo.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Scenario {
+    pub bounds: BoundsConfig,
+    pub weights: CrowGlobalProps,
+    pub species: Vec<SpeciesConfig>,
+    pub predators: Vec<PredatorConfig>,
+    pub obstacles: Vec<ObstacleConfig>,
+    /// Hills to fly over instead of a flat ground.
+    pub terrain: Option<TerrainConfig>,
+    /// Food scattered on the ground for hungry crows.
+    pub food: Option<FoodConfig>,
+}
+
+impl Default for Scenario {
+    fn default() -> Self {
+        Scenario {
+            bounds: BoundsConfig::default(),
+            weights: CrowGlobalProps::default(),
+            species: vec![SpeciesConfig::default()],
+            predators: Vec::new(),
+            obstacles: Vec::new(),
+            terrain: None,
+            food: None,
+        }
+    }
+}
+
+/// Something went wrong loading a scenario.
+#[derive(Debug)]
+pub enum ScenarioError {
+    Io(std::io::Error),
+    Parse(ron::Error),
+    /// It parsed, but makes no sense (e.g. somewhere to spawn which is
+    /// inside out).
+    Invalid(String),
+    /// The heightmap (at the given path) wouldn't load.
+    Terrain(String, image::ImageError),
+}
+
+impl fmt::Display for ScenarioError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScenarioError::Io(err) => write!(f, "couldn't read scenario: {}", err),
+            ScenarioError::Parse(err) => write!(f, "couldn't parse scenario: {}", err),
+            ScenarioError::Invalid(message) => write!(f, "invalid scenario: {}", message),
+            ScenarioError::Terrain(path, err) => write!(f, "couldn't load heightmap {}: {}", path, err),
+        }
+    }
+}
+
+impl std::error::Error for ScenarioError {}
+
+impl Scenario {
+    pub fn load(path: impl AsRef<Path>) -> Result<Scenario, ScenarioError> {
+        let text = fs::read_to_string(path).map_err(ScenarioError::Io)?;
+        Scenario::from_ron(&text)
+    }
+
+    pub fn from_ron(text: &str) -> Result<Scenario, ScenarioError> {
+        let scenario: Scenario = ron::from_str(text).map_err(ScenarioError::Parse)?;
+        scenario.validate()?;
+        Ok(scenario)
+    }
+
+    /// The ground, if the scenario has a heightmap.
+    pub fn load_terrain(&self) -> Result<Option<Terrain>, ScenarioError> {
+        let bounds = self.bounds.to_bounds();
+        self.terrain.as_ref()
+            .map(|config| config.load(&bounds).map_err(|err| ScenarioError::Terrain(config.heightmap.clone(), err)))
+            .transpose()
+    }
+
+    /// Checks for things which would otherwise only go wrong once we start
+    /// spawning, like bounds too small to leave any room inside the margin.
+    pub fn validate(&self) -> Result<(), ScenarioError> {
+        let (min, max) = (self.bounds.min, self.bounds.max);
+        if (0..3).any(|axis| min[axis] >= max[axis]) {
+            return Err(ScenarioError::Invalid(format!("the bounds' min {:?} isn't below their max {:?}", min, max)));
+        }
+
+        let bounds = self.bounds.to_bounds();
+        for species in self.species.iter().filter(|species| species.count > 0) {
+            check_spawn_box(&species.name, species.spawn_box(&bounds), &[0, 1, 2])?;
+        }
+        for predator in self.predators.iter().filter(|predator| predator.count > 0) {
+            check_spawn_box(&predator.name, predator.spawn_box(&bounds), &[0, 1, 2])?;
+        }
+        if let Some(food) = self.food.as_ref().filter(|food| food.count > 0) {
+            // Food goes on the ground, so only x and z count.
+            check_spawn_box("food", food.spawn_box(&bounds), &[0, 2])?;
+        }
+        Ok(())
+    }
+}
+
+fn check_spawn_box(what: &str, (min, max): ([f32; 3], [f32; 3]), axes: &[usize]) -> Result<(), ScenarioError> {
+    if axes.iter().any(|axis| min[*axis] > max[*axis]) {
+        return Err(ScenarioError::Invalid(format!(
+            "{} has nowhere to spawn: from {:?} to {:?} is inside out (is the region the wrong way round, or are the bounds too small for the margin?)",
+            what, min, max
+        )));
+    }
+    Ok(())
+}
+
+/// A heightmap for the ground. See `Terrain`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TerrainConfig {
+    /// A grayscale image, relative to the assets folder.
+    pub heightmap: String,
+    /// How high white is above the floor of the bounds.
+    pub max_height: f32,
+    /// How far above the ground birds want to keep.
+    #[serde(default = "TerrainConfig::default_clearance")]
+    pub clearance: f32,
+}
+
+impl TerrainConfig {
+    fn default_clearance() -> f32 {
+        20.0
+    }
+
+    pub fn load(&self, bounds: &Bounds) -> image::ImageResult<Terrain> {
+        Terrain::from_image(Path::new("assets").join(&self.heightmap), bounds, self.max_height, self.clearance)
+    }
+}
+
+/// The overall bounds of the simulation. See `Bounds`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BoundsConfig {
+    pub cell_size: f32,
+    pub min: [f32; 3],
+    pub max: [f32; 3],
+    pub margin: f32,
+    pub mode: BoundaryMode,
+    pub shape: ArenaShape,
+}
+
+impl Default for BoundsConfig {
+    fn default() -> Self {
+        BoundsConfig {
+            cell_size: 50.0,
+            min: [0.0, 0.0, 0.0],
+            max: [600.0, 250.0, 600.0],
+            margin: 50.0,
+            mode: BoundaryMode::Steer,
+            shape: ArenaShape::Box,
+        }
+    }
+}
+
+impl BoundsConfig {
+    pub fn to_bounds(&self) -> Bounds {
+        let mut bounds = Bounds::new(
+            self.cell_size,
+            self.min[0],
+            self.max[0],
+            self.min[1],
+            self.max[1],
+            self.min[2],
+            self.max[2],
+            self.margin,
+        );
+        bounds.mode = self.mode;
+        bounds.shape = self.shape;
+        bounds
+    }
+}
+
+/// A box to spawn things in.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RegionConfig {
+    pub min: [f32; 3],
+    pub max: [f32; 3],
+}
+
+/// The box to spawn things in, as (min, max). If there's no region,
+/// anywhere in the bounds well clear of the margin.
+pub fn spawn_box(region: Option<&RegionConfig>, bounds: &Bounds) -> ([f32; 3], [f32; 3]) {
+    match region {
+        Some(region) => (region.min, region.max),
+        None => {
+            let m = bounds.margin + 50.0;
+            (
+                [bounds.x_min + m, bounds.y_min + m, bounds.z_min + m],
+                [bounds.x_max - m, bounds.y_max - m, bounds.z_max - m],
+            )
+        }
+    }
+}
+
+/// Something static to fly around. See `ObstacleShape`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ObstacleConfig {
+    pub position: [f32; 3],
+    pub shape: ObstacleShapeConfig,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ObstacleShapeConfig {
+    Sphere { radius: f32 },
+    Box { size: [f32; 3] },
+    Cylinder { radius: f32, height: f32 },
+}
+
+impl ObstacleConfig {
+    pub fn to_shape(&self) -> ObstacleShape {
+        match self.shape {
+            ObstacleShapeConfig::Sphere { radius } => ObstacleShape::Sphere { radius },
+            ObstacleShapeConfig::Box { size } => ObstacleShape::Box {
+                half_extents: Vec3::from(size) * 0.5,
+            },
+            ObstacleShapeConfig::Cylinder { radius, height } => ObstacleShape::Cylinder { radius, height },
+        }
+    }
+}
+
+/// One kind of bird, and how many of them to spawn.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SpeciesConfig {
+    pub name: String,
+    /// The glTF model under `assets/`, if any.
+    pub model: Option<String>,
+    pub scale: f32,
+    pub count: usize,
+    /// Where to spawn. If not given, anywhere in the bounds well clear of
+    /// the margin.
+    pub spawn_region: Option<RegionConfig>,
+    pub initial_speed: f32,
+    pub flight: FlightConfig,
+    /// Fly with gravity, lift and drag rather than the simpler default
+    /// flight model.
+    pub aerodynamics: Option<AerodynamicsConfig>,
+    pub boids: BoidsConfig,
+    pub observer: ObserverConfig,
+    pub stamina: StaminaConfig,
+    pub wingbeat: WingbeatConfig,
+    pub hunger: HungerConfig,
+    /// Somewhere for the whole species to go, if anywhere.
+    pub goal: Option<GoalConfig>,
+}
+
+impl Default for SpeciesConfig {
+    fn default() -> Self {
+        SpeciesConfig {
+            name: String::from("House Crow"),
+            model: Some(String::from("house_crow.glb")),
+            scale: 0.1,
+            count: 180,
+            spawn_region: None,
+            initial_speed: 75.0,
+            flight: FlightConfig::default(),
+            aerodynamics: None,
+            boids: BoidsConfig::default(),
+            observer: ObserverConfig::default(),
+            stamina: StaminaConfig::default(),
+            wingbeat: WingbeatConfig::default(),
+            hunger: HungerConfig::default(),
+            goal: None,
+        }
+    }
+}
+
+impl SpeciesConfig {
+    /// The box to spawn this species in, as (min, max).
+    pub fn spawn_box(&self, bounds: &Bounds) -> ([f32; 3], [f32; 3]) {
+        spawn_box(self.spawn_region.as_ref(), bounds)
+    }
+}
+
+/// Where a species is headed. See `Seek`, `Arrive` and `Patrol`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum GoalConfig {
+    Seek { point: [f32; 3] },
+    Arrive { point: [f32; 3], slowing_radius: f32 },
+    Patrol { waypoints: Vec<[f32; 3]>, reach_radius: f32, looping: bool },
+}
+
+/// One kind of predator, and how many of them to spawn.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PredatorConfig {
+    pub name: String,
+    /// The glTF model under `assets/`, if any.
+    pub model: Option<String>,
+    pub scale: f32,
+    pub count: usize,
+    /// Where to spawn. If not given, anywhere in the bounds well clear of
+    /// the margin.
+    pub spawn_region: Option<RegionConfig>,
+    pub initial_speed: f32,
+    pub flight: FlightConfig,
+}
+
+impl Default for PredatorConfig {
+    fn default() -> Self {
+        // We don't have a hawk model, so it's a big, fast crow for now.
+        PredatorConfig {
+            name: String::from("Hawk"),
+            model: Some(String::from("house_crow.glb")),
+            scale: 0.18,
+            count: 1,
+            spawn_region: None,
+            initial_speed: 90.0,
+            flight: FlightConfig {
+                accel_max: 5.0,
+                spd_min: 60.0,
+                spd_max: 130.0,
+                ang_spd_x_max: 1.0,
+                ang_spd_y_max: 3.0,
+                ang_z_from_y_spd: 0.33,
+            },
+        }
+    }
+}
+
+impl PredatorConfig {
+    /// The box to spawn these predators in, as (min, max).
+    pub fn spawn_box(&self, bounds: &Bounds) -> ([f32; 3], [f32; 3]) {
+        spawn_box(self.spawn_region.as_ref(), bounds)
+    }
+}
+
+/// See `FlyerProps`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FlightConfig {
+    pub accel_max: f32,
+    pub spd_min: f32,
+    pub spd_max: f32,
+    pub ang_spd_x_max: f32,
+    pub ang_spd_y_max: f32,
+    pub ang_z_from_y_spd: f32,
+}
+
+impl FlightConfig {
+    pub fn to_props(&self) -> FlyerProps {
+        FlyerProps {
+            accel_max: self.accel_max,
+            spd_min: self.spd_min,
+            spd_max: self.spd_max,
+            ang_spd_x_max: self.ang_spd_x_max,
+            ang_spd_y_max: self.ang_spd_y_max,
+            ang_z_from_y_spd: self.ang_z_from_y_spd,
+            ..Default::default()
+        }
+    }
+}
+
+/// See `Aerodynamics`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AerodynamicsConfig {
+    pub gravity: f32,
+    pub lift: f32,
+    pub lift_slope: f32,
+    pub stall_angle: f32,
+    pub drag: f32,
+    pub flap_thrust: f32,
+}
+
+impl Default for AerodynamicsConfig {
+    fn default() -> Self {
+        // Soaring at the crows' usual speed sinks slowly, and flapping
+        // flat out is enough to climb.
+        AerodynamicsConfig {
+            gravity: 30.0,
+            lift: 0.0045,
+            lift_slope: 5.0,
+            stall_angle: 0.3,
+            drag: 0.0025,
+            flap_thrust: 20.0,
+        }
+    }
+}
+
+impl AerodynamicsConfig {
+    pub fn to_aerodynamics(&self, velocity: Vec3) -> Aerodynamics {
+        Aerodynamics {
+            velocity,
+            gravity: self.gravity,
+            lift: self.lift,
+            lift_slope: self.lift_slope,
+            stall_angle: self.stall_angle,
+            drag: self.drag,
+            flap_thrust: self.flap_thrust,
+            throttle: 0.0,
+            max_throttle: 1.0,
+        }
+    }
+}
+
+impl Default for FlightConfig {
+    fn default() -> Self {
+        FlightConfig {
+            accel_max: 3.0,
+            spd_min: 50.0,
+            spd_max: 100.0,
+            ang_spd_x_max: 0.7,
+            ang_spd_y_max: 3.0,
+            ang_z_from_y_spd: 0.33,
+        }
+    }
+}
+
+/// Each individual's own weights, which are scaled by the global ones.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BoidsConfig {
+    pub separation: f32,
+    pub alignment: f32,
+    pub cohesion: f32,
+    pub keep_level: f32,
+    /// How close a predator has to be before we flee.
+    pub flee_radius: f32,
+    /// How much room to leave around obstacles.
+    pub obstacle_clearance: f32,
+    /// How far ahead to look for obstacles, in seconds.
+    pub obstacle_look_ahead_secs: f32,
+}
+
+impl Default for BoidsConfig {
+    fn default() -> Self {
+        BoidsConfig {
+            separation: 0.10,
+            alignment: 0.20,
+            cohesion: 0.02,
+            keep_level: 0.1,
+            flee_radius: 80.0,
+            obstacle_clearance: 15.0,
+            obstacle_look_ahead_secs: 1.5,
+        }
+    }
+}
+
+/// See `Observer`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ObserverConfig {
+    pub perception_radius: f32,
+    pub fov_half_angle: f32,
+    pub max_neighbours: Option<usize>,
+}
+
+impl Default for ObserverConfig {
+    fn default() -> Self {
+        ObserverConfig {
+            perception_radius: 50.0,
+            fov_half_angle: 2.6, // ~150 degrees, so a blind spot behind
+            max_neighbours: Some(7),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StaminaConfig {
+    pub initial: f32,
+    /// While flying.
+    pub recover_per_second: f32,
+    /// While perched.
+    pub rest_per_second: f32,
+    pub flap_usage_per_second: f32,
+    /// Land when stamina (averaged over the last half minute or so) gets
+    /// down to about this.
+    pub land_below: f32,
+    /// Take off again once stamina is back up to this.
+    pub take_off_above: f32,
+}
+
+impl Default for StaminaConfig {
+    fn default() -> Self {
+        StaminaConfig {
+            initial: 70.0,
+            recover_per_second: 10.0,
+            rest_per_second: 25.0,
+            flap_usage_per_second: 30.0,
+            land_below: 60.0,
+            take_off_above: 95.0,
+        }
+    }
+}
+
+/// How hunger works for a species, from 0 (full) to 100 (starving).
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HungerConfig {
+    pub initial: f32,
+    pub gain_per_second: f32,
+    pub eat_per_second: f32,
+    /// Around where we go looking for food (if we've the stamina for it).
+    pub hungry_above: f32,
+    /// Stop eating once we're down to this.
+    pub sated_below: f32,
+}
+
+impl Default for HungerConfig {
+    fn default() -> Self {
+        HungerConfig {
+            initial: 20.0,
+            gain_per_second: 1.0,
+            eat_per_second: 20.0,
+            hungry_above: 60.0,
+            sated_below: 5.0,
+        }
+    }
+}
+
+/// Food sources to scatter over the ground. See `FoodSource`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FoodConfig {
+    pub count: usize,
+    /// How much each one holds (in hunger) when full.
+    pub capacity: f32,
+    pub regrow_per_second: f32,
+    /// Where to scatter them (only x and z count). If not given, anywhere
+    /// in the bounds well clear of the margin.
+    pub spawn_region: Option<RegionConfig>,
+}
+
+impl Default for FoodConfig {
+    fn default() -> Self {
+        FoodConfig {
+            count: 8,
+            capacity: 300.0,
+            regrow_per_second: 5.0,
+            spawn_region: None,
+        }
+    }
+}
+
+impl FoodConfig {
+    /// The box to scatter food in, as (min, max).
+    pub fn spawn_box(&self, bounds: &Bounds) -> ([f32; 3], [f32; 3]) {
+        spawn_box(self.spawn_region.as_ref(), bounds)
+    }
+}
+
+/// What flapping and soaring let a species do. See `Wingbeat`. While
+/// flapping, `accel_max` comes from the `FlightConfig`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WingbeatConfig {
+    pub flap_climb_max: f32,
+    pub soar_accel_max: f32,
+    pub soar_climb_max: f32,
+    /// Only for species without aerodynamics, which sink by themselves.
+    pub soar_sink_rate: f32,
+}
+
+impl Default for WingbeatConfig {
+    fn default() -> Self {
+        WingbeatConfig {
+            flap_climb_max: 40.0,
+            soar_accel_max: 1.0,
+            soar_climb_max: 0.0,
+            soar_sink_rate: 4.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_scenario_file_matches_default() {
+        let scenario = Scenario::from_ron(include_str!("../assets/scenarios/default.ron")).unwrap();
+        assert_eq!(scenario, Scenario::default());
+    }
+
+    #[test]
+    fn missing_fields_take_defaults() {
+        let scenario = Scenario::from_ron("(species: [(name: \"Jungle Crow\", count: 12)])").unwrap();
+        assert_eq!(scenario.bounds, BoundsConfig::default());
+        assert_eq!(scenario.species.len(), 1);
+        assert_eq!(scenario.species[0].name, "Jungle Crow");
+        assert_eq!(scenario.species[0].count, 12);
+        assert_eq!(scenario.species[0].flight, FlightConfig::default());
+    }
+
+    #[test]
+    fn hawk_scenario_has_a_predator() {
+        let scenario = Scenario::from_ron(include_str!("../assets/scenarios/hawk.ron")).unwrap();
+        assert_eq!(scenario.predators.len(), 1);
+        assert_eq!(scenario.predators[0].name, "Hawk");
+    }
+
+    #[test]
+    fn trees_scenario_has_obstacles() {
+        let scenario = Scenario::from_ron(include_str!("../assets/scenarios/trees.ron")).unwrap();
+        assert!(!scenario.obstacles.is_empty());
+        assert!(scenario.obstacles.iter().any(|obstacle| matches!(obstacle.to_shape(), ObstacleShape::Cylinder { .. })));
+    }
+
+    #[test]
+    fn hills_scenario_has_terrain() {
+        let scenario = Scenario::from_ron(include_str!("../assets/scenarios/hills.ron")).unwrap();
+        let terrain = scenario.terrain.unwrap();
+        assert_eq!(terrain.heightmap, "terrain/hills.png");
+        assert_eq!(terrain.clearance, 20.0);
+    }
+
+    #[test]
+    fn boundary_modes_load() {
+        let scenario = Scenario::from_ron(include_str!("../assets/scenarios/wrap.ron")).unwrap();
+        assert_eq!(scenario.bounds.mode, BoundaryMode::Wrap);
+        let scenario = Scenario::from_ron(include_str!("../assets/scenarios/arena.ron")).unwrap();
+        assert_eq!(scenario.bounds.mode, BoundaryMode::Reflect);
+        assert_eq!(scenario.bounds.shape, ArenaShape::Sphere);
+    }
+
+    #[test]
+    fn roost_scenario_has_goals() {
+        let scenario = Scenario::from_ron(include_str!("../assets/scenarios/roost.ron")).unwrap();
+        assert!(matches!(scenario.species[0].goal, Some(GoalConfig::Arrive { .. })));
+        match &scenario.species[1].goal {
+            Some(GoalConfig::Patrol { waypoints, looping, .. }) => {
+                assert_eq!(waypoints.len(), 4);
+                assert!(*looping);
+            }
+            other => panic!("expected a patrol, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn mixed_scenario_has_two_species() {
+        let scenario = Scenario::from_ron(include_str!("../assets/scenarios/mixed.ron")).unwrap();
+        assert_eq!(scenario.species.len(), 2);
+        assert_eq!(scenario.species[1].name, "Common Myna");
+        assert_eq!(scenario.species[1].boids.flee_radius, BoidsConfig::default().flee_radius);
+    }
+
+    #[test]
+    fn gliders_scenario_is_aerodynamic() {
+        let scenario = Scenario::from_ron(include_str!("../assets/scenarios/gliders.ron")).unwrap();
+        assert_eq!(scenario.species[0].aerodynamics, Some(AerodynamicsConfig::default()));
+    }
+
+    #[test]
+    fn forage_scenario_has_food() {
+        let scenario = Scenario::from_ron(include_str!("../assets/scenarios/forage.ron")).unwrap();
+        assert_eq!(scenario.food.unwrap().count, 10);
+        assert_eq!(scenario.species[0].hunger.gain_per_second, 2.0);
+        assert_eq!(scenario.species[0].hunger.eat_per_second, HungerConfig::default().eat_per_second);
+    }
+
+    #[test]
+    fn bad_scenario_is_an_error() {
+        assert!(Scenario::from_ron("(bounds: 12)").is_err());
+    }
+
+    #[test]
+    fn inside_out_spawn_region_is_an_error() {
+        let text = "(predators: [(count: 1, spawn_region: Some((min: (100.0, 50.0, 100.0), max: (50.0, 100.0, 200.0))))])";
+        assert!(matches!(Scenario::from_ron(text), Err(ScenarioError::Invalid(_))));
+    }
+
+    #[test]
+    fn bounds_too_small_for_margin_is_an_error() {
+        let text = "(bounds: (min: (0.0, 0.0, 0.0), max: (100.0, 100.0, 100.0)))";
+        assert!(matches!(Scenario::from_ron(text), Err(ScenarioError::Invalid(_))));
+        assert!(matches!(Scenario::from_ron("(bounds: (max: (0.0, 250.0, 600.0)))"), Err(ScenarioError::Invalid(_))));
+    }
+}
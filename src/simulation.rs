@@ -0,0 +1,263 @@
+use std::ops::{Deref, DerefMut};
+
+use bevy::{
+    prelude::*,
+    ecs::schedule::ShouldRun,
+    transform::TransformSystem,
+};
+use rand::prelude::*;
+
+/// The simulation plugin. Adds the `SimulationStage`, which runs at a fixed
+/// tick rate (see `SimulationSettings`) so that the flock behaves the same
+/// whatever the frame rate. Must be added before any plugins which add
+/// systems to the stage.
+pub struct Simulation;
+
+impl Plugin for Simulation {
+    fn build(&self, app: &mut App) {
+        let settings = app.world
+            .get_resource::<SimulationSettings>()
+            .cloned()
+            .unwrap_or_default();
+
+        let seed = settings.seed.unwrap_or_else(rand::random);
+
+        app
+            .insert_resource(SimulationSeed(seed))
+            .insert_resource(SimulationRng(StdRng::seed_from_u64(seed)))
+            .insert_resource(SimulationTime {
+                delta_seconds: settings.step_seconds() as f32,
+                tick: 0,
+                overstep: 0.0,
+            })
+            .insert_resource(settings)
+            .init_resource::<SimulationClock>()
+            .add_stage_after(
+                CoreStage::Update,
+                SimulationStage,
+                SystemStage::parallel().with_run_criteria(simulation_run_criteria),
+            )
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                interpolate_transforms_system
+                    .label(SimulationLabel::Interpolate)
+                    .before(TransformSystem::TransformPropagate),
+            );
+    }
+}
+
+/// The stage that the simulation runs in.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, StageLabel)]
+pub struct SimulationStage;
+
+/// Labels for the parts of the simulation, which run in this order
+/// each tick (except `Interpolate`, which runs once a frame).
+#[derive(Debug, Clone, PartialEq, Eq, Hash, SystemLabel)]
+pub enum SimulationLabel {
+    /// Working out who can see whom.
+    Observe,
+    /// The boids rules.
+    Boids,
+    /// Working out other goals, like keeping in bounds.
+    Goals,
+    /// Adding up the steering behaviours (see `SteeringBehaviour`).
+    Accumulate,
+    /// Turning boids and other goals into a goal velocity.
+    Steer,
+    /// Steering and moving.
+    Flight,
+    /// Sampling the flock for the `Recorder`.
+    Record,
+    /// Measuring the flock for `Metrics`.
+    Metrics,
+    /// Splitting the flyers into flocks for `Clusters`.
+    Clusters,
+    /// Interpolating transforms for rendering, in `CoreStage::PostUpdate`.
+    Interpolate,
+}
+
+/// Settings for the simulation's fixed timestep. Insert this before adding
+/// the `Simulation` plugin to change them.
+#[derive(Clone, Debug)]
+pub struct SimulationSettings {
+    /// Ticks per second.
+    pub tick_rate: f64,
+    /// Run exactly one tick per update no matter how much real time has
+    /// passed, e.g. to run headless as fast as possible.
+    pub lockstep: bool,
+    /// The most ticks we'll run in one update before giving up on catching up.
+    pub max_ticks_per_update: u32,
+    /// The seed for `SimulationRng`. A random one is picked if this isn't set.
+    pub seed: Option<u64>,
+    /// Stop ticking once this many ticks have run.
+    pub max_ticks: Option<u64>,
+}
+
+impl Default for SimulationSettings {
+    fn default() -> Self {
+        SimulationSettings {
+            tick_rate: 60.0,
+            lockstep: false,
+            max_ticks_per_update: 5,
+            seed: None,
+            max_ticks: None,
+        }
+    }
+}
+
+impl SimulationSettings {
+    pub fn step_seconds(&self) -> f64 {
+        1.0 / self.tick_rate
+    }
+}
+
+/// The seed that `SimulationRng` started from. Run again with the same seed
+/// (e.g. `--seed`) to get the same flock.
+#[derive(Clone, Copy, Debug)]
+pub struct SimulationSeed(pub u64);
+
+/// Where everything random in the simulation should come from, rather
+/// than `thread_rng()`, so that runs can be reproduced.
+pub struct SimulationRng(pub StdRng);
+
+impl Deref for SimulationRng {
+    type Target = StdRng;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl DerefMut for SimulationRng {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+/// Time as the simulation sees it. Use this rather than `Time` in anything
+/// which runs in the `SimulationStage`.
+#[derive(Debug)]
+pub struct SimulationTime {
+    delta_seconds: f32,
+    tick: u64,
+    overstep: f32,
+}
+
+impl SimulationTime {
+    /// The length of one tick.
+    pub fn delta_seconds(&self) -> f32 {
+        self.delta_seconds
+    }
+
+    /// How many ticks have been run.
+    pub fn tick(&self) -> u64 {
+        self.tick
+    }
+
+    /// How much simulated time has passed.
+    pub fn elapsed_seconds(&self) -> f64 {
+        self.tick as f64 * self.delta_seconds as f64
+    }
+
+    /// How far we are between the last tick and the next, from 0 to 1.
+    pub fn overstep(&self) -> f32 {
+        self.overstep
+    }
+}
+
+/// Keeps track of the real time we still need to simulate.
+#[derive(Default)]
+struct SimulationClock {
+    accumulator: f64,
+    ticking: bool,
+}
+
+/// Add this to anything the simulation moves so it's drawn smoothly
+/// between ticks. The simulation sees the transform from the latest tick,
+/// everything else sees one interpolated between the last two ticks.
+#[derive(Component, Debug, Clone)]
+pub struct Interpolated {
+    previous: Transform,
+    current: Transform,
+}
+
+impl Interpolated {
+    pub fn new(transform: Transform) -> Interpolated {
+        Interpolated {
+            previous: transform,
+            current: transform,
+        }
+    }
+
+    /// Call after moving something a long way in one go so it doesn't
+    /// appear to slide there.
+    pub fn snap(&mut self, transform: Transform) {
+        self.previous = transform;
+        self.current = transform;
+    }
+}
+
+/// Decides whether to run another tick. This also does the interpolation
+/// bookkeeping, as it's run before and after every tick.
+fn simulation_run_criteria(
+    time: Res<Time>,
+    settings: Res<SimulationSettings>,
+    mut clock: ResMut<SimulationClock>,
+    mut sim_time: ResMut<SimulationTime>,
+    mut query: Query<(&mut Transform, &mut Interpolated)>,
+) -> ShouldRun {
+    let step = settings.step_seconds();
+
+    if clock.ticking {
+        // A tick just finished, so remember where it left everything.
+        for (transform, mut interpolated) in query.iter_mut() {
+            interpolated.current = *transform;
+        }
+    } else {
+        // First check this update. Put back the simulation's transforms.
+        for (mut transform, interpolated) in query.iter_mut() {
+            *transform = interpolated.current;
+        }
+
+        clock.accumulator = if settings.lockstep {
+            step
+        } else {
+            (clock.accumulator + time.delta_seconds_f64()).min(step * settings.max_ticks_per_update as f64)
+        };
+    }
+
+    let finished = settings.max_ticks.map_or(false, |max_ticks| sim_time.tick >= max_ticks);
+    if clock.accumulator >= step && !finished {
+        clock.accumulator -= step;
+        clock.ticking = true;
+        sim_time.delta_seconds = step as f32;
+        sim_time.tick += 1;
+        for (_, mut interpolated) in query.iter_mut() {
+            interpolated.previous = interpolated.current;
+        }
+        ShouldRun::YesAndCheckAgain
+    } else {
+        clock.ticking = false;
+        sim_time.overstep = (clock.accumulator / step) as f32;
+        ShouldRun::No
+    }
+}
+
+fn interpolate_transforms_system(
+    settings: Res<SimulationSettings>,
+    sim_time: Res<SimulationTime>,
+    mut query: Query<(&mut Transform, &Interpolated)>,
+) {
+    if settings.lockstep {
+        return;
+    }
+
+    let t = sim_time.overstep();
+    for (mut transform, interpolated) in query.iter_mut() {
+        let previous = &interpolated.previous;
+        let current = &interpolated.current;
+        transform.translation = previous.translation.lerp(current.translation, t);
+        transform.rotation = previous.rotation.slerp(current.rotation, t);
+        transform.scale = previous.scale.lerp(current.scale, t);
+    }
+}
@@ -0,0 +1,182 @@
+use std::marker::PhantomData;
+
+use bevy::{
+    ecs::system::Resource,
+    prelude::*,
+};
+use crate::flight::{Flyer, Grounded};
+use crate::simulation::{SimulationLabel, SimulationStage, SimulationTime};
+
+/// The steering plugin. Every registered `SteeringBehaviour` adds what it
+/// wants into a `SteeringAccumulator`, and the total nudges the `Flyer`'s
+/// goal velocity. Anything with an accumulator flies with whichever
+/// behaviours it happens to have.
+pub struct Steering;
+
+impl Plugin for Steering {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<SteeringRegistry>()
+            .add_system_to_stage(
+                SimulationStage,
+                steering_system
+                    .label(SimulationLabel::Steer)
+                    .after(SimulationLabel::Accumulate),
+            );
+    }
+}
+
+/// A component which knows which way it would like to go, and how badly.
+/// Register it with `App::add_steering_behaviour`.
+pub trait SteeringBehaviour: Component {
+    /// The change in velocity we'd like.
+    fn steer(&self) -> Vec3;
+    /// How badly we'd like it (usually the component's own weight).
+    fn weight(&self) -> f32;
+}
+
+/// Where the steering behaviours on an entity add up each tick.
+#[derive(Component, Debug, Default)]
+pub struct SteeringAccumulator {
+    /// One entry per registered behaviour, so the total doesn't depend on
+    /// the order the behaviours' systems happen to run in.
+    contributions: Vec<Vec3>,
+}
+
+impl SteeringAccumulator {
+    pub fn add(&mut self, slot: usize, steer: Vec3) {
+        if self.contributions.len() <= slot {
+            self.contributions.resize(slot + 1, Vec3::ZERO);
+        }
+        self.contributions[slot] += steer;
+    }
+
+    /// Adds everything up and starts again from nothing.
+    pub fn take(&mut self) -> Vec3 {
+        let mut total = Vec3::ZERO;
+        for contribution in self.contributions.iter_mut() {
+            total += *contribution;
+            *contribution = Vec3::ZERO;
+        }
+        total
+    }
+}
+
+/// A global scale for one kind of steering behaviour, on top of each
+/// entity's own weight (e.g. for a UI slider).
+pub struct SteeringScale<T: SteeringBehaviour> {
+    pub scale: f32,
+    slot: usize,
+    marker: PhantomData<T>,
+}
+
+/// Hands out accumulator slots to steering behaviours as they're registered.
+#[derive(Default)]
+pub struct SteeringRegistry {
+    count: usize,
+}
+
+pub trait SteeringAppExt {
+    /// Makes `T` count towards the steering of anything with a
+    /// `SteeringAccumulator`, scaled by a `SteeringScale<T>` resource (1 to
+    /// begin with). Registering the same behaviour twice does nothing.
+    fn add_steering_behaviour<T: SteeringBehaviour>(&mut self) -> &mut Self;
+
+    /// Keeps the `SteeringScale<T>` in step with a resource `R` (e.g. some
+    /// UI sliders), reading it with `scale_of` whenever it changes.
+    /// Registers `T` too, if it isn't already.
+    fn add_steering_scale<T: SteeringBehaviour, R: Resource>(&mut self, scale_of: fn(&R) -> f32) -> &mut Self;
+}
+
+impl SteeringAppExt for App {
+    fn add_steering_behaviour<T: SteeringBehaviour>(&mut self) -> &mut Self {
+        if self.world.contains_resource::<SteeringScale<T>>() {
+            return self;
+        }
+
+        let slot = {
+            let mut registry = self.world.get_resource_or_insert_with(SteeringRegistry::default);
+            registry.count += 1;
+            registry.count - 1
+        };
+
+        self
+            .insert_resource(SteeringScale::<T> {
+                scale: 1.0,
+                slot,
+                marker: PhantomData,
+            })
+            .add_system_to_stage(
+                SimulationStage,
+                accumulate_steering_system::<T>
+                    .label(SimulationLabel::Accumulate)
+                    .after(SimulationLabel::Goals),
+            )
+    }
+
+    fn add_steering_scale<T: SteeringBehaviour, R: Resource>(&mut self, scale_of: fn(&R) -> f32) -> &mut Self {
+        self
+            .add_steering_behaviour::<T>()
+            .add_system_to_stage(
+                SimulationStage,
+                (move |source: Option<Res<R>>, mut scale: ResMut<SteeringScale<T>>| {
+                    if let Some(source) = source {
+                        if source.is_changed() {
+                            scale.scale = scale_of(&source);
+                        }
+                    }
+                })
+                .before(SimulationLabel::Accumulate),
+            )
+    }
+}
+
+fn accumulate_steering_system<T: SteeringBehaviour>(
+    scale: Res<SteeringScale<T>>,
+    mut query: Query<(&T, &mut SteeringAccumulator), Without<Grounded>>,
+) {
+    for (behaviour, mut accumulator) in query.iter_mut() {
+        let weight = behaviour.weight() * scale.scale;
+        if weight != 0.0 {
+            accumulator.add(scale.slot, behaviour.steer() * weight);
+        }
+    }
+}
+
+/// Turns the accumulated steering into the goal velocity for a flyer.
+/// Grounded flyers don't steer, or save any up for when they take off.
+pub fn steering_system(
+    time: Res<SimulationTime>,
+    mut query: Query<(&mut Flyer, &mut SteeringAccumulator), Without<Grounded>>,
+) {
+    for (mut flyer, mut accumulator) in query.iter_mut() {
+        flyer.goal_velocity += time.delta_seconds() * accumulator.take();
+
+        // Clamp our goal velocity within our properties.
+        let mag_sqr = flyer.goal_velocity.length_squared();
+        if mag_sqr > (flyer.props.spd_max * flyer.props.spd_max)
+        {
+            let vel_n = flyer.goal_velocity.normalize();
+            flyer.goal_velocity = vel_n * flyer.props.spd_max;
+        } else if mag_sqr < (flyer.props.spd_min * flyer.props.spd_min)
+        {
+            let vel_n = flyer.goal_velocity.normalize_or_zero();
+            flyer.goal_velocity = vel_n * flyer.props.spd_min;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accumulator_adds_up_and_resets() {
+        let mut accumulator = SteeringAccumulator::default();
+        accumulator.add(2, Vec3::X);
+        accumulator.add(0, Vec3::Y);
+        accumulator.add(2, Vec3::X);
+        assert_eq!(accumulator.take(), Vec3::new(2.0, 1.0, 0.0));
+        assert_eq!(accumulator.take(), Vec3::ZERO);
+    }
+}
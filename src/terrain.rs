@@ -0,0 +1,177 @@
+use std::path::Path;
+
+use bevy::{
+    prelude::*,
+    render::{mesh::Indices, render_resource::PrimitiveTopology},
+};
+use image::error::{ImageError, ParameterError, ParameterErrorKind};
+use crate::bounds::Bounds;
+use crate::scenario::{Scenario, ScenarioError};
+
+/// The ground, as a grid of heights stretched over the bounds on x and z.
+/// Insert it as a resource and `KeepInBounds` keeps flyers `clearance`
+/// above it instead of above `Bounds::y_min`.
+pub struct Terrain {
+    /// Number of samples along x.
+    pub width: usize,
+    /// Number of samples along z.
+    pub depth: usize,
+    /// Heights, row by row along x.
+    pub heights: Vec<f32>,
+    pub x_min: f32,
+    pub z_min: f32,
+    pub x_size: f32,
+    pub z_size: f32,
+    /// How far above the ground flyers want to keep.
+    pub clearance: f32,
+}
+
+/// Inserts the scenario's `Terrain`, if it has a heightmap. Call it while
+/// building the app, so the ground is there for the startup systems.
+pub fn insert_scenario_terrain(app: &mut App, scenario: &Scenario) -> Result<(), ScenarioError> {
+    if let Some(terrain) = scenario.load_terrain()? {
+        app.insert_resource(terrain);
+    }
+    Ok(())
+}
+
+impl Terrain {
+    /// Loads a grayscale heightmap, where black is the floor of the bounds
+    /// and white is `max_height` above it.
+    pub fn from_image(path: impl AsRef<Path>, bounds: &Bounds, max_height: f32, clearance: f32) -> image::ImageResult<Terrain> {
+        let image = image::open(path)?.into_luma8();
+        let (width, depth) = image.dimensions();
+        Terrain::from_luma(width as usize, depth as usize, image.as_raw(), bounds, max_height, clearance)
+    }
+
+    /// A heightmap from raw grayscale pixels, row by row. It needs at least
+    /// 2x2 of them to have anything to interpolate between.
+    pub fn from_luma(width: usize, depth: usize, pixels: &[u8], bounds: &Bounds, max_height: f32, clearance: f32) -> image::ImageResult<Terrain> {
+        if width < 2 || depth < 2 {
+            return Err(ImageError::Parameter(ParameterError::from_kind(ParameterErrorKind::Generic(
+                format!("a heightmap needs at least 2x2 pixels, not {}x{}", width, depth),
+            ))));
+        }
+        if pixels.len() != width * depth {
+            return Err(ImageError::Parameter(ParameterError::from_kind(ParameterErrorKind::DimensionMismatch)));
+        }
+        Ok(Terrain {
+            width,
+            depth,
+            heights: pixels.iter().map(|&p| bounds.y_min + p as f32 / 255.0 * max_height).collect(),
+            x_min: bounds.x_min,
+            z_min: bounds.z_min,
+            x_size: bounds.x_size,
+            z_size: bounds.z_size,
+            clearance,
+        })
+    }
+
+    fn sample(&self, x: usize, z: usize) -> f32 {
+        self.heights[x + z * self.width]
+    }
+
+    /// The height of the ground at (x, z), interpolated between samples.
+    /// Outside the bounds we use the height at the nearest edge.
+    pub fn height_at(&self, x: f32, z: f32) -> f32 {
+        let u = ((x - self.x_min) / self.x_size).clamp(0.0, 1.0) * (self.width - 1) as f32;
+        let v = ((z - self.z_min) / self.z_size).clamp(0.0, 1.0) * (self.depth - 1) as f32;
+        let x0 = (u.floor() as usize).min(self.width - 2);
+        let z0 = (v.floor() as usize).min(self.depth - 2);
+        let tx = u - x0 as f32;
+        let tz = v - z0 as f32;
+
+        let near = lerp(self.sample(x0, z0), self.sample(x0 + 1, z0), tx);
+        let far = lerp(self.sample(x0, z0 + 1), self.sample(x0 + 1, z0 + 1), tx);
+        lerp(near, far, tz)
+    }
+
+    /// The lowest a flyer at (x, z) wants to be.
+    pub fn floor_at(&self, x: f32, z: f32) -> f32 {
+        self.height_at(x, z) + self.clearance
+    }
+
+    /// A mesh of the ground, one vertex per sample.
+    pub fn to_mesh(&self) -> Mesh {
+        let step_x = self.x_size / (self.width - 1) as f32;
+        let step_z = self.z_size / (self.depth - 1) as f32;
+
+        let mut positions = Vec::with_capacity(self.width * self.depth);
+        let mut normals = Vec::with_capacity(self.width * self.depth);
+        let mut uvs = Vec::with_capacity(self.width * self.depth);
+        for z in 0..self.depth {
+            for x in 0..self.width {
+                positions.push([self.x_min + x as f32 * step_x, self.sample(x, z), self.z_min + z as f32 * step_z]);
+
+                let left = self.sample(x.saturating_sub(1), z);
+                let right = self.sample((x + 1).min(self.width - 1), z);
+                let back = self.sample(x, z.saturating_sub(1));
+                let front = self.sample(x, (z + 1).min(self.depth - 1));
+                let normal = Vec3::new((left - right) / step_x, 2.0, (back - front) / step_z).normalize();
+                normals.push(normal.to_array());
+
+                uvs.push([x as f32 / (self.width - 1) as f32, z as f32 / (self.depth - 1) as f32]);
+            }
+        }
+
+        let mut indices = Vec::with_capacity((self.width - 1) * (self.depth - 1) * 6);
+        for z in 0..self.depth - 1 {
+            for x in 0..self.width - 1 {
+                let i = (x + z * self.width) as u32;
+                let w = self.width as u32;
+                indices.extend_from_slice(&[i, i + w, i + 1, i + 1, i + w, i + w + 1]);
+            }
+        }
+
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+        mesh.set_indices(Some(Indices::U32(indices)));
+        mesh
+    }
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bounds() -> Bounds {
+        Bounds::new(50.0, 0.0, 100.0, 0.0, 200.0, 0.0, 100.0, 10.0)
+    }
+
+    #[test]
+    fn heights_scale_from_the_floor() {
+        let terrain = Terrain::from_luma(2, 2, &[0, 255, 0, 255], &bounds(), 50.0, 5.0).unwrap();
+        assert_eq!(terrain.height_at(0.0, 0.0), 0.0);
+        assert_eq!(terrain.height_at(100.0, 0.0), 50.0);
+        assert_eq!(terrain.floor_at(100.0, 100.0), 55.0);
+    }
+
+    #[test]
+    fn height_is_interpolated() {
+        let terrain = Terrain::from_luma(2, 2, &[0, 255, 0, 255], &bounds(), 50.0, 5.0).unwrap();
+        assert!((terrain.height_at(50.0, 30.0) - 25.0).abs() < 1e-4);
+        let terrain = Terrain::from_luma(3, 2, &[0, 255, 0, 0, 255, 0], &bounds(), 100.0, 0.0).unwrap();
+        assert!((terrain.height_at(50.0, 50.0) - 100.0).abs() < 1e-4);
+        assert!((terrain.height_at(25.0, 50.0) - 50.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn height_is_clamped_outside_bounds() {
+        let terrain = Terrain::from_luma(2, 2, &[0, 255, 0, 255], &bounds(), 50.0, 5.0).unwrap();
+        assert_eq!(terrain.height_at(-50.0, 50.0), 0.0);
+        assert_eq!(terrain.height_at(500.0, -50.0), 50.0);
+    }
+
+    #[test]
+    fn tiny_heightmaps_are_an_error() {
+        assert!(Terrain::from_luma(1, 1, &[0], &bounds(), 50.0, 5.0).is_err());
+        assert!(Terrain::from_luma(2, 1, &[0, 255], &bounds(), 50.0, 5.0).is_err());
+        assert!(Terrain::from_luma(2, 2, &[0, 255, 0], &bounds(), 50.0, 5.0).is_err());
+    }
+}